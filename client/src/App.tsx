@@ -11,7 +11,7 @@ import Footer from './components/Footer'
 import Create from './screens/Create'
 import Join, { JoinRouteData } from './screens/Join'
 import About from './screens/About'
-import { h24ToTimeRange, API_URL } from './utils'
+import { h24ToTimeRange, API_URL, CSRF_HEADERS } from './utils'
 import { WebSocketProvider } from './contexts/WebSocketContext'
 import { DaySelectMode } from './components/DateSelect'
 import { Button } from './components/ui/button'
@@ -45,6 +45,7 @@ const App = () => {
 
               fetch(`${API_URL}/api/auth`, {
                 method: 'POST',
+                headers: CSRF_HEADERS,
                 credentials: 'include'
               })
                 .then(res => {
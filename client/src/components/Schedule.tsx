@@ -3,7 +3,14 @@ import { FontAwesomeIcon } from '@fortawesome/react-fontawesome'
 import { useEffect, useRef, useState } from 'react'
 import { Button } from './ui/button'
 import { ScheduleData } from '@/types'
-import { API_URL, SITE_URL, getOtherUserColor, h12To24 } from '@/utils'
+import {
+  API_URL,
+  CSRF_HEADERS,
+  SITE_URL,
+  getOtherUserColor,
+  h12To24,
+  solveChallenge
+} from '@/utils'
 import tinycolor from 'tinycolor2'
 import { Slider } from '@/components/ui/slider'
 import { NavigateFunction } from 'react-router-dom'
@@ -57,7 +64,7 @@ export const shareRoom = (
     return false
   }
 
-  let req = JSON.stringify({
+  const req = {
     event_name: scheduleData.eventName,
     schedule_type: scheduleData.dates.mode,
     dates: scheduleData.dates.dates,
@@ -76,13 +83,17 @@ export const shareRoom = (
     slot_length: scheduleData.slotLength,
     schedule: scheduleData.userSchedule,
     timezone: scheduleData.timezone
-  })
+  }
 
-  fetch(`${API_URL}/api/rooms`, {
-    method: 'POST',
-    body: req,
-    credentials: 'include'
-  })
+  solveChallenge()
+    .then(challenge =>
+      fetch(`${API_URL}/api/rooms`, {
+        method: 'POST',
+        body: JSON.stringify({ ...req, challenge }),
+        headers: CSRF_HEADERS,
+        credentials: 'include'
+      })
+    )
     .then(res => {
       if (res.status === 200) {
         res.json().then(resJSON => {
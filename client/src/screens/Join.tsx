@@ -27,7 +27,13 @@ import {
   TooltipProvider,
   TooltipTrigger
 } from '@/components/ui/tooltip'
-import { API_URL, SITE_URL, getOtherUserColor, useDebounce } from '@/utils'
+import {
+  API_URL,
+  CSRF_HEADERS,
+  SITE_URL,
+  getOtherUserColor,
+  useDebounce
+} from '@/utils'
 
 export type JoinRouteData = {
   scheduleData: ScheduleData
@@ -213,6 +219,7 @@ const Join = () => {
   const deleteRoom = async () => {
     await fetch(`${API_URL}/api/rooms/${roomUid}`, {
       method: 'DELETE',
+      headers: CSRF_HEADERS,
       credentials: 'include'
     })
       .then(res => {
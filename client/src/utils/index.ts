@@ -13,6 +13,49 @@ export const API_URL =
     ? 'http://localhost:3632'
     : 'https://cmon.rsvp'
 
+// The server refuses state-changing requests without this header, which a
+// cross-site form or script can't add without passing CORS
+export const CSRF_HEADERS = { 'X-Requested-With': 'cmon.rsvp' }
+
+const leadingZeroBits = (bytes: Uint8Array) => {
+  let bits = 0
+  for (const byte of bytes) {
+    if (byte === 0) {
+      bits += 8
+      continue
+    }
+    bits += Math.clz32(byte) - 24
+    break
+  }
+  return bits
+}
+
+// Room creation may require a proof-of-work, find a suffix whose SHA-256
+// with the nonce starts with `difficulty` zero bits
+export const solveChallenge = async (): Promise<
+  { nonce: string; solution: string } | undefined
+> => {
+  const res = await fetch(`${API_URL}/api/challenge`, {
+    credentials: 'include'
+  })
+  if (!res.ok) return undefined
+
+  const { nonce, difficulty }: { nonce: string; difficulty: number } =
+    await res.json()
+  if (difficulty === 0) return undefined
+
+  const encoder = new TextEncoder()
+  for (let i = 0; ; i++) {
+    const solution = i.toString(36)
+    const hash = await crypto.subtle.digest(
+      'SHA-256',
+      encoder.encode(nonce + solution)
+    )
+    if (leadingZeroBits(new Uint8Array(hash)) >= difficulty)
+      return { nonce, solution }
+  }
+}
+
 export const h12To24 = (hour: number, isAM: boolean) => {
   if (isAM && hour === 12) return 0
   if (!isAM && hour !== 12) return hour + 12
@@ -0,0 +1 @@
+ALTER TABLE rooms ADD COLUMN read_only_token VARCHAR(64) DEFAULT NULL;
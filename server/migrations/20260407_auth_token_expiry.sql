@@ -0,0 +1,2 @@
+ALTER TABLE users ADD COLUMN auth_token_expires_at TIMESTAMP NULL DEFAULT NULL;
+UPDATE users SET auth_token_expires_at = NOW() + INTERVAL 400 DAY WHERE auth_token IS NOT NULL;
@@ -0,0 +1,9 @@
+CREATE TABLE room_bans (
+    id INT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    room_uid VARCHAR(36),
+    user_uid VARCHAR(36),
+    name VARCHAR(64),
+    banned_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE KEY unique_ban (user_uid, room_uid)
+);
+CREATE INDEX idx_ban_room_uid ON room_bans(room_uid);
@@ -0,0 +1,2 @@
+ALTER TABLE rooms ADD COLUMN require_approval BOOL DEFAULT FALSE;
+ALTER TABLE users_of_rooms ADD COLUMN is_pending BOOL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+ALTER TABLE rooms ADD COLUMN if_needed JSON;
+UPDATE rooms SET if_needed = JSON_ARRAY();
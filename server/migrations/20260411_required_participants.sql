@@ -0,0 +1 @@
+ALTER TABLE users_of_rooms ADD COLUMN is_required BOOL DEFAULT FALSE;
@@ -0,0 +1,5 @@
+ALTER TABLE rooms ADD COLUMN start_minute SMALLINT UNSIGNED;
+ALTER TABLE rooms ADD COLUMN end_minute SMALLINT UNSIGNED;
+UPDATE rooms SET start_minute = time_min * 60, end_minute = time_max * 60;
+ALTER TABLE rooms DROP COLUMN time_min;
+ALTER TABLE rooms DROP COLUMN time_max;
@@ -0,0 +1,2 @@
+ALTER TABLE rooms ADD COLUMN response_deadline TIMESTAMP NULL DEFAULT NULL;
+ALTER TABLE rooms ADD COLUMN deadline_announced BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,7 @@
+CREATE TABLE room_templates (
+    user_uid VARCHAR(36),
+    name VARCHAR(64),
+    settings JSON,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE KEY unique_template (user_uid, name)
+);
@@ -0,0 +1,2 @@
+-- Poll rooms (schedule_type=3) list their choices here
+ALTER TABLE rooms ADD COLUMN options JSON;
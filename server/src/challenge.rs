@@ -0,0 +1,143 @@
+use crate::utils::generate_auth_token;
+
+use async_std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const CHALLENGE_TTL: Duration = Duration::from_secs(300);
+const MAX_OUTSTANDING: usize = 10_000;
+pub const MAX_DIFFICULTY: u8 = 32;
+
+#[derive(Serialize)]
+pub struct Challenge {
+    pub nonce: String,
+    pub difficulty: u8,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChallengeSolution {
+    pub nonce: String,
+    pub solution: String,
+}
+
+/// Proof-of-work challenges for anonymous room creation.
+///
+/// A solution is valid when `sha256(nonce + solution)` starts with at least
+/// `difficulty` zero bits. Difficulty 0 turns the challenge off.
+#[derive(Clone)]
+pub struct Challenges {
+    difficulty: Arc<AtomicU8>,
+    issued: Arc<Mutex<Issued>>,
+}
+
+/// Outstanding nonces, and the order they were issued in so the oldest can
+/// go first. Redeemed nonces linger in `order` until they reach its front.
+#[derive(Default)]
+struct Issued {
+    nonces: HashMap<String, (Instant, u8)>,
+    order: VecDeque<(Instant, String)>,
+}
+
+impl Issued {
+    /// Drops expired nonces, then the oldest ones until fewer than `keep` are left
+    fn evict(&mut self, keep: usize) {
+        while let Some((issued_at, nonce)) = self.order.front() {
+            if issued_at.elapsed() < CHALLENGE_TTL
+                && self.nonces.len() < keep
+                && self.nonces.contains_key(nonce)
+            {
+                break;
+            }
+            self.nonces.remove(nonce);
+            self.order.pop_front();
+        }
+    }
+}
+
+impl Challenges {
+    /// Initial difficulty comes from `POW_DIFFICULTY`, it can be changed at runtime
+    pub fn from_env() -> Self {
+        let difficulty = std::env::var("POW_DIFFICULTY")
+            .ok()
+            .and_then(|difficulty| difficulty.parse().ok())
+            .unwrap_or(0u8)
+            .min(MAX_DIFFICULTY);
+
+        Self {
+            difficulty: Arc::new(AtomicU8::new(difficulty)),
+            issued: Default::default(),
+        }
+    }
+
+    pub fn difficulty(&self) -> u8 {
+        self.difficulty.load(Ordering::Relaxed)
+    }
+
+    pub fn set_difficulty(&self, difficulty: u8) {
+        self.difficulty
+            .store(difficulty.min(MAX_DIFFICULTY), Ordering::Relaxed);
+    }
+
+    pub fn is_required(&self) -> bool {
+        self.difficulty() > 0
+    }
+
+    /// Nothing is kept while the challenge is off, there's nothing to verify
+    pub async fn issue(&self) -> Challenge {
+        let difficulty = self.difficulty();
+        let nonce = generate_auth_token();
+        if difficulty == 0 {
+            return Challenge { nonce, difficulty };
+        }
+
+        // When full of live challenges, forget the oldest rather than grow
+        let mut issued = self.issued.lock().await;
+        issued.evict(MAX_OUTSTANDING);
+        let now = Instant::now();
+        issued.nonces.insert(nonce.clone(), (now, difficulty));
+        issued.order.push_back((now, nonce.clone()));
+
+        Challenge { nonce, difficulty }
+    }
+
+    /// Checks the solution against the difficulty the nonce was issued with.
+    /// Every nonce can only be redeemed once.
+    pub async fn verify(&self, solution: &ChallengeSolution) -> bool {
+        let Some((issued_at, difficulty)) = self.issued.lock().await.nonces.remove(&solution.nonce)
+        else {
+            return false;
+        };
+
+        if issued_at.elapsed() > CHALLENGE_TTL {
+            return false;
+        }
+
+        let hash = Sha256::digest(format!("{}{}", solution.nonce, solution.solution));
+        leading_zero_bits(&hash) >= u32::from(difficulty)
+    }
+
+    pub async fn prune(&self) {
+        self.issued.lock().await.evict(MAX_OUTSTANDING + 1);
+    }
+
+    pub async fn outstanding(&self) -> usize {
+        self.issued.lock().await.nonces.len()
+    }
+}
+
+fn leading_zero_bits(bytes: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in bytes {
+        if *byte == 0 {
+            bits += 8;
+        } else {
+            bits += byte.leading_zeros();
+            break;
+        }
+    }
+    bits
+}
@@ -0,0 +1,241 @@
+use crate::auth::revoke_tokens;
+use crate::challenge::MAX_DIFFICULTY;
+use crate::models::{State, UserOfRoom};
+use crate::room::{fetch_room, remove_room};
+use crate::utils::format_timestamp;
+
+use serde::Deserialize;
+use tide::prelude::*;
+use tide::Request;
+use tide::Response;
+use tide::StatusCode;
+
+pub async fn stats(req: Request<State>) -> tide::Result {
+    let state = req.state();
+
+    let (room_count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM rooms")
+        .fetch_one(&state.db_pool)
+        .await?;
+    let (user_count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM users")
+        .fetch_one(&state.db_pool)
+        .await?;
+
+    let (live_rooms, live_connections) = {
+        let rooms = state.rooms.lock().await;
+        (rooms.len(), rooms.values().map(|room| room.len()).sum::<usize>())
+    };
+
+    let mut rate_limits = Vec::new();
+    for limiter in state.rate_limits.all() {
+        rate_limits.push(limiter.stats().await);
+    }
+
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(json!({
+        "rooms": room_count,
+        "users": user_count,
+        "live_rooms": live_rooms,
+        "live_connections": live_connections,
+        "rate_limits": rate_limits,
+        "challenge": {
+            "difficulty": state.challenges.difficulty(),
+            "outstanding": state.challenges.outstanding().await,
+        },
+    }));
+    Ok(response)
+}
+
+pub async fn list_rooms(req: Request<State>) -> tide::Result {
+    #[derive(Deserialize)]
+    struct ListRoomsQuery {
+        q: Option<String>,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    }
+    let query: ListRoomsQuery = req.query()?;
+
+    // Matches either the room uid or the event name
+    let pattern = format!("%{}%", query.q.unwrap_or_default());
+    let limit = query.limit.unwrap_or(50).min(200);
+    let offset = query.offset.unwrap_or(0);
+
+    let rows: Vec<(String, String, u8, time_new::OffsetDateTime, i64)> = sqlx::query_as(
+        r#"
+        SELECT rooms.uid, rooms.event_name, rooms.schedule_type, rooms.expires_at,
+               COUNT(users_of_rooms.user_uid)
+        FROM rooms
+        LEFT JOIN users_of_rooms ON users_of_rooms.room_uid = rooms.uid
+        WHERE rooms.uid LIKE ? OR rooms.event_name LIKE ?
+        GROUP BY rooms.uid
+        ORDER BY rooms.expires_at DESC
+        LIMIT ? OFFSET ?
+        "#,
+    )
+    .bind(&pattern)
+    .bind(&pattern)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(&req.state().db_pool)
+    .await?;
+
+    let live_rooms = req.state().rooms.lock().await;
+    let rooms: Vec<_> = rows
+        .into_iter()
+        .map(|(uid, event_name, schedule_type, expires_at, user_count)| {
+            let live_connections = live_rooms.get(&uid).map(|room| room.len()).unwrap_or(0);
+            json!({
+                "uid": uid,
+                "event_name": event_name,
+                "schedule_type": schedule_type,
+                "expires_at": format_timestamp(expires_at),
+                "users": user_count,
+                "live_connections": live_connections,
+            })
+        })
+        .collect();
+
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(json!({ "rooms": rooms }));
+    Ok(response)
+}
+
+pub async fn get_room(req: Request<State>) -> tide::Result {
+    let room_uid = req.param("room_uid")?.to_uppercase();
+
+    let room = match fetch_room(&req.state().db_pool, &room_uid, false).await {
+        Ok(room) => room,
+        Err(sqlx::Error::RowNotFound) => return Ok(Response::new(StatusCode::NotFound)),
+        Err(e) => return Err(e.into()),
+    };
+
+    let users_of_room: Vec<UserOfRoom> =
+        sqlx::query_as("SELECT * FROM users_of_rooms WHERE room_uid=?")
+            .bind(&room_uid)
+            .fetch_all(&req.state().db_pool)
+            .await?;
+
+    let connected: Vec<String> = match req.state().rooms.lock().await.get(&room_uid) {
+        Some(room) => room.keys().cloned().collect(),
+        None => Vec::new(),
+    };
+
+    let participants: Vec<_> = users_of_room
+        .into_iter()
+        .map(|user| {
+            json!({
+                "user_uid": user.user_uid,
+                "name": user.name,
+                "is_owner": user.is_owner,
+                "is_required": user.is_required,
+                "is_absent": user.is_absent,
+                "absent_reason": user.absent_reason,
+                "connected": connected.contains(&user.user_uid),
+            })
+        })
+        .collect();
+
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(json!({
+        "uid": room.uid,
+        "event_name": room.event_name,
+        "schedule_type": room.schedule_type,
+        "dates": serde_json::from_str::<serde_json::Value>(&room.dates)?,
+        "days_of_week": serde_json::from_str::<serde_json::Value>(&room.days_of_week)?,
+        "time_range": { "from_minute": room.start_minute, "to_minute": room.end_minute },
+        "day_windows": room
+            .day_windows
+            .as_deref()
+            .map(serde_json::from_str::<serde_json::Value>)
+            .transpose()?,
+        "options": room
+            .options
+            .as_deref()
+            .map(serde_json::from_str::<serde_json::Value>)
+            .transpose()?,
+        "slot_length": room.slot_length,
+        "timezone": room.timezone,
+        "has_read_only_token": room.read_only_token.is_some(),
+        "expires_at": format_timestamp(room.expires_at),
+        "participants": participants,
+        "live_connections": connected.len(),
+    }));
+    Ok(response)
+}
+
+pub async fn delete_room(req: Request<State>) -> tide::Result {
+    let room_uid = req.param("room_uid")?.to_uppercase();
+
+    remove_room(req.state(), &room_uid, None).await?;
+    println!("Admin deleted room {}", room_uid);
+
+    Ok(Response::new(StatusCode::Ok))
+}
+
+pub async fn extend_room(mut req: Request<State>) -> tide::Result {
+    #[derive(Deserialize)]
+    struct ExtendRoomReq {
+        days: u32,
+    }
+    let ExtendRoomReq { days } = match req.body_json::<ExtendRoomReq>().await {
+        Ok(res) => res,
+        Err(_) => return Ok(Response::new(StatusCode::BadRequest)),
+    };
+    let room_uid = req.param("room_uid")?.to_uppercase();
+
+    // Extends from now if the room is already past its expiry
+    let result = sqlx::query(
+        r#"
+        UPDATE rooms
+        SET expires_at = GREATEST(expires_at, NOW()) + INTERVAL ? DAY
+        WHERE uid=?
+        "#,
+    )
+    .bind(days)
+    .bind(&room_uid)
+    .execute(&req.state().db_pool)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Ok(Response::new(StatusCode::NotFound));
+    }
+
+    let (expires_at,): (time_new::OffsetDateTime,) =
+        sqlx::query_as("SELECT expires_at FROM rooms WHERE uid=?")
+            .bind(&room_uid)
+            .fetch_one(&req.state().db_pool)
+            .await?;
+
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(json!({ "expires_at": format_timestamp(expires_at) }));
+    Ok(response)
+}
+
+pub async fn set_challenge_difficulty(mut req: Request<State>) -> tide::Result {
+    #[derive(Deserialize)]
+    struct SetDifficultyReq {
+        difficulty: u8,
+    }
+    let SetDifficultyReq { difficulty } = match req.body_json::<SetDifficultyReq>().await {
+        Ok(res) => res,
+        Err(_) => return Ok(Response::new(StatusCode::BadRequest)),
+    };
+    if difficulty > MAX_DIFFICULTY {
+        return Ok(Response::new(StatusCode::BadRequest));
+    }
+
+    req.state().challenges.set_difficulty(difficulty);
+    println!("Admin set challenge difficulty to {}", difficulty);
+
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(json!({ "difficulty": difficulty }));
+    Ok(response)
+}
+
+pub async fn revoke_user_tokens(req: Request<State>) -> tide::Result {
+    let user_uid = req.param("user_uid")?;
+
+    revoke_tokens(req.state(), user_uid).await?;
+    println!("Admin revoked tokens of user {}", user_uid);
+
+    Ok(Response::new(StatusCode::Ok))
+}
@@ -11,23 +11,70 @@ use time::Duration;
 use time::OffsetDateTime;
 use uuid::Uuid;
 
+/// Days a token stays valid after it's issued, `AUTH_TOKEN_TTL_DAYS`
+fn token_ttl_days() -> i64 {
+    std::env::var("AUTH_TOKEN_TTL_DAYS")
+        .ok()
+        .and_then(|days| days.parse().ok())
+        .unwrap_or(400)
+}
+
+/// Tokens with fewer days than this left are replaced by `authenticate`,
+/// `AUTH_TOKEN_RENEW_DAYS`
+fn token_renew_days() -> i64 {
+    std::env::var("AUTH_TOKEN_RENEW_DAYS")
+        .ok()
+        .and_then(|days| days.parse().ok())
+        .unwrap_or(30)
+}
+
+fn auth_cookie(auth_token: String) -> Cookie<'static> {
+    let is_secure = std::env::var("FRONTEND_URL")
+        .map(|url| url.starts_with("https"))
+        .unwrap_or(false);
+
+    Cookie::build("auth_token", auth_token)
+        .http_only(true)
+        .secure(is_secure)
+        .path("/")
+        .expires(OffsetDateTime::now_utc() + Duration::days(token_ttl_days()))
+        .same_site(tide::http::cookies::SameSite::Strict)
+        .finish()
+}
+
 pub async fn authenticate(req: Request<State>) -> tide::Result {
     let mut response = Response::new(StatusCode::Ok);
 
-    if get_user_uid_from_cookie(&req).await == None {
-        let mut transaction: Transaction<'_, MySql> = req.state().db_pool.begin().await?;
+    match get_user_uid_from_cookie(&req).await {
+        None => {
+            let mut transaction: Transaction<'_, MySql> = req.state().db_pool.begin().await?;
 
-        match signup(&mut transaction).await {
-            Ok((_signup_result, cookie)) => {
-                response.insert_cookie(cookie);
-            }
-            Err(err) => {
-                println!("{}", err);
-                return Ok(Response::new(StatusCode::InternalServerError));
+            match signup(&mut transaction).await {
+                Ok((_signup_result, cookie)) => {
+                    response.insert_cookie(cookie);
+                }
+                Err(err) => {
+                    println!("{}", err);
+                    return Ok(Response::new(StatusCode::InternalServerError));
+                }
             }
+
+            transaction.commit().await?;
         }
+        Some(user_uid) => {
+            // Sliding renewal, so active users never hit the expiry
+            let (expires_soon,): (bool,) = sqlx::query_as(
+                "SELECT auth_token_expires_at < NOW() + INTERVAL ? DAY FROM users WHERE uid=?",
+            )
+            .bind(token_renew_days())
+            .bind(&user_uid)
+            .fetch_one(&req.state().db_pool)
+            .await?;
 
-        transaction.commit().await?;
+            if expires_soon {
+                response.insert_cookie(rotate_token(req.state(), &user_uid).await?);
+            }
+        }
     }
 
     Ok(response)
@@ -38,34 +85,71 @@ pub async fn signup(
 ) -> Result<(String, Cookie<'static>), sqlx::Error> {
     let user_uid = Uuid::new_v4().to_string();
     let auth_token = generate_auth_token();
-
-    let is_secure = std::env::var("FRONTEND_URL")
-        .map(|url| url.starts_with("https"))
-        .unwrap_or(false);
+    let ttl_days = token_ttl_days();
 
     match sqlx::query!(
         r#"
-        INSERT INTO users (uid, auth_token, default_name)
-        VALUES (?, ?, '')
+        INSERT INTO users (uid, auth_token, auth_token_expires_at, default_name)
+        VALUES (?, ?, NOW() + INTERVAL ? DAY, '')
         "#,
         user_uid,
-        auth_token
+        auth_token,
+        ttl_days
     )
     .execute(&mut **transaction)
     .await
     {
-        Ok(_) => {
-            return Ok((
-                user_uid,
-                Cookie::build("auth_token", auth_token)
-                    .http_only(true)
-                    .secure(is_secure)
-                    .path("/")
-                    .expires(OffsetDateTime::now_utc() + Duration::days(400))
-                    .same_site(tide::http::cookies::SameSite::Strict)
-                    .finish(),
-            ))
-        }
+        Ok(_) => return Ok((user_uid, auth_cookie(auth_token))),
         Err(err) => return Err(err),
     }
 }
+
+/// Replaces the user's token with a fresh one, the old token stops working
+pub async fn rotate_token(state: &State, user_uid: &str) -> Result<Cookie<'static>, sqlx::Error> {
+    let auth_token = generate_auth_token();
+
+    sqlx::query(
+        r#"
+        UPDATE users
+        SET auth_token=?, auth_token_expires_at=NOW() + INTERVAL ? DAY
+        WHERE uid=?
+        "#,
+    )
+    .bind(&auth_token)
+    .bind(token_ttl_days())
+    .bind(user_uid)
+    .execute(&state.db_pool)
+    .await?;
+
+    Ok(auth_cookie(auth_token))
+}
+
+/// Invalidates every token of the user and disconnects their open websockets
+pub async fn revoke_tokens(state: &State, user_uid: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET auth_token=NULL, auth_token_expires_at=NULL WHERE uid=?")
+        .bind(user_uid)
+        .execute(&state.db_pool)
+        .await?;
+
+    for room in state.rooms.lock().await.values() {
+        if let Some(user_wsc) = room.get(user_uid) {
+            let _ = user_wsc
+                .send(tide_websockets::Message::Close(None))
+                .await;
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn revoke_all_tokens(req: Request<State>) -> tide::Result {
+    let Some(user_uid) = get_user_uid_from_cookie(&req).await else {
+        return Ok(Response::new(StatusCode::Unauthorized));
+    };
+
+    revoke_tokens(req.state(), &user_uid).await?;
+
+    let mut response = Response::new(StatusCode::Ok);
+    response.remove_cookie(Cookie::build("auth_token", "").path("/").finish());
+    Ok(response)
+}
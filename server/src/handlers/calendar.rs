@@ -0,0 +1,210 @@
+use crate::icalendar::{self, Event};
+use crate::models::State;
+use crate::room::{fetch_room, parse_finalized, save_user_schedule, SaveRefused};
+use crate::schedule::{self, column_dates, local_instant, Grid, Preference};
+use crate::utils::get_user_uid_from_cookie;
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use tide::prelude::*;
+use tide::Request;
+use tide::Response;
+use tide::StatusCode;
+
+const MAX_ICS_BYTES: usize = 2_000_000;
+
+/// A slot's start and end in UTC
+type SlotSpan = (DateTime<Utc>, DateTime<Utc>);
+
+/// The finalized slot range as a calendar event, weekly rooms repeat every week
+pub async fn event_ics(req: Request<State>) -> tide::Result {
+    let room_uid = req.param("room_uid")?.to_uppercase();
+
+    let room = match fetch_room(&req.state().db_pool, &room_uid, false).await {
+        Ok(room) => room,
+        Err(sqlx::Error::RowNotFound) => return Ok(Response::new(StatusCode::NotFound)),
+        Err(e) => return Err(e.into()),
+    };
+    let Some(finalized) = parse_finalized(&room)? else {
+        return Ok(Response::new(StatusCode::NotFound));
+    };
+
+    // An unknown zone still exports, as floating local time
+    let timezone: Option<Tz> = room.timezone.parse().ok();
+    let weekly = room.schedule_type == 1;
+
+    // Weekly rooms start from the first occurrence from today on, in the room's zone
+    let today: NaiveDate = match timezone {
+        Some(tz) => Utc::now().with_timezone(&tz).date_naive(),
+        None => Utc::now().date_naive(),
+    };
+    let Some(date) = column_dates(&room, today)?
+        .get(finalized.day_index)
+        .copied()
+        .flatten()
+    else {
+        return Ok(Response::new(StatusCode::NotFound));
+    };
+
+    let Some(window) = schedule::column_windows(&room)?.get(finalized.day_index).copied() else {
+        return Ok(Response::new(StatusCode::NotFound));
+    };
+    let midnight = date.and_hms_opt(0, 0, 0).unwrap_or_default();
+
+    let frontend_url =
+        std::env::var("FRONTEND_URL").unwrap_or_else(|_| "https://cmon.rsvp".to_string());
+    let host = frontend_url
+        .split("://")
+        .last()
+        .unwrap_or_default()
+        .trim_end_matches('/');
+    let room_url = format!("{}/{}", frontend_url.trim_end_matches('/'), room_uid);
+
+    let event = Event {
+        uid: format!("{}@{}", room_uid, host),
+        summary: room.event_name.clone(),
+        description: format!("Scheduled on cmon.rsvp: {}", room_url),
+        url: room_url,
+        start: midnight + Duration::minutes(i64::from(window.slot_start(finalized.start_slot))),
+        end: midnight + Duration::minutes(i64::from(window.slot_start(finalized.end_slot))),
+        timezone,
+        weekly,
+    };
+
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_content_type("text/calendar; charset=utf-8");
+    response.insert_header(
+        "Content-Disposition",
+        format!("attachment; filename=\"{}.ics\"", room_uid),
+    );
+    response.set_body(icalendar::calendar(&event, Utc::now()));
+    Ok(response)
+}
+
+/// Proposes the user's availability from an uploaded calendar: free wherever
+/// they have nothing on, if needed where it's only tentative. `?apply=true`
+/// saves it as their schedule right away.
+pub async fn import_ics(mut req: Request<State>) -> tide::Result {
+    #[derive(Deserialize)]
+    struct ImportQuery {
+        apply: Option<bool>,
+    }
+    let query: ImportQuery = match req.query() {
+        Ok(query) => query,
+        Err(_) => return Ok(Response::new(StatusCode::BadRequest)),
+    };
+    let apply = query.apply.unwrap_or(false);
+    let room_uid = req.param("room_uid")?.to_uppercase();
+
+    if req.len().is_some_and(|len| len > MAX_ICS_BYTES) {
+        return Ok(Response::new(StatusCode::PayloadTooLarge));
+    }
+    let ics = match req.body_string().await {
+        Ok(ics) if ics.len() > MAX_ICS_BYTES => {
+            return Ok(Response::new(StatusCode::PayloadTooLarge))
+        }
+        Ok(ics) => ics,
+        Err(_) => return Ok(Response::new(StatusCode::BadRequest)),
+    };
+
+    let user_uid = get_user_uid_from_cookie(&req).await;
+    if apply && user_uid.is_none() {
+        return Ok(Response::new(StatusCode::Unauthorized));
+    }
+
+    let room = match fetch_room(&req.state().db_pool, &room_uid, false).await {
+        Ok(room) => room,
+        Err(sqlx::Error::RowNotFound) => return Ok(Response::new(StatusCode::NotFound)),
+        Err(e) => return Err(e.into()),
+    };
+
+    let tz: Tz = room.timezone.parse().unwrap_or(Tz::UTC);
+    let windows = schedule::column_windows(&room)?;
+    let schedule: Grid = serde_json::from_str(&room.schedule)?;
+    let dates = column_dates(&room, Utc::now().with_timezone(&tz).date_naive())?;
+
+    // Slot bounds in UTC, `None` for columns without a date we can read
+    let slot_spans: Vec<Vec<Option<SlotSpan>>> = schedule
+        .iter()
+        .enumerate()
+        .map(|(day_index, day)| {
+            (0..day.len())
+                .map(|slot| {
+                    let date = dates.get(day_index).copied().flatten()?;
+                    let window = windows.get(day_index)?;
+                    let start = local_instant(tz, date, window.slot_start(slot))?;
+                    Some((start, start + Duration::minutes(i64::from(window.slot_length))))
+                })
+                .collect()
+        })
+        .collect();
+
+    let spans = slot_spans.iter().flatten().flatten();
+    let (Some(from), Some(to)) = (
+        spans.clone().map(|(start, _)| *start).min(),
+        spans.map(|(_, end)| *end).max(),
+    ) else {
+        return Ok(Response::new(StatusCode::BadRequest));
+    };
+
+    let busy = match icalendar::busy_intervals(&ics, tz, from, to) {
+        Ok(busy) => busy,
+        Err(e) => {
+            println!("Rejected calendar import: {}", e);
+            return Ok(Response::new(StatusCode::BadRequest));
+        }
+    };
+
+    let user_schedule: Vec<Vec<u8>> = slot_spans
+        .iter()
+        .map(|day| {
+            day.iter()
+                .map(|span| {
+                    let Some((start, end)) = span else {
+                        return schedule::UNAVAILABLE;
+                    };
+                    let overlapping = busy
+                        .iter()
+                        .filter(|busy| busy.start < *end && busy.end > *start);
+                    let mut level = schedule::AVAILABLE;
+                    for busy in overlapping {
+                        if !busy.tentative {
+                            return schedule::UNAVAILABLE;
+                        }
+                        level = schedule::IF_NEEDED;
+                    }
+                    level
+                })
+                .collect()
+        })
+        .collect();
+
+    if let (true, Some(user_uid)) = (apply, &user_uid) {
+        let preferences: Vec<Vec<Preference>> = user_schedule
+            .iter()
+            .map(|day| day.iter().map(|&level| Preference::Level(level)).collect())
+            .collect();
+
+        let saved = save_user_schedule(req.state(), &room_uid, user_uid, String::new(), &preferences)
+            .await
+            .map_err(|e| (e.is::<SaveRefused>(), e.to_string()));
+        match saved {
+            Ok(()) => {}
+            Err((true, e)) => {
+                println!("Refused calendar import: {}", e);
+                return Ok(Response::new(StatusCode::Forbidden));
+            }
+            Err((false, e)) => {
+                return Err(tide::Error::from_str(StatusCode::InternalServerError, e));
+            }
+        }
+    }
+
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(json!({
+        "user_schedule": user_schedule,
+        "applied": apply,
+    }));
+    Ok(response)
+}
@@ -1,3 +1,6 @@
+pub mod admin;
 pub mod auth;
+pub mod calendar;
 pub mod room;
+pub mod template;
 pub mod websocket;
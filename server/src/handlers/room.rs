@@ -1,8 +1,20 @@
 use crate::auth::signup;
+use crate::challenge::ChallengeSolution;
+use crate::template;
 use crate::models::{
-    CreateRoomReq, GetRoomRes, Room, RoomDeletedPing, ScheduleDates, State, TimeRange,
+    CreateRoomReq, DayWindow, Finalized, GetRoomRes, Room, RoomDeletedPing, ScheduleDates, State,
+    TimeRange, UserOfRoom, ViewerGrid, READ_ONLY_VIEWER_PREFIX,
+};
+use crate::schedule::{
+    self, format_minute, Grid, Preference, TimeWindow, ViewerLayout, ROOM_DATE_FORMAT,
+};
+use crate::utils::{
+    escape_html, format_timestamp, generate_auth_token, generate_id, get_read_only_token,
+    get_user_uid_from_cookie, get_viewer_timezone,
 };
-use crate::utils::{generate_id, get_user_uid_from_cookie};
+
+use chrono::{Datelike, NaiveDate, Utc};
+use chrono_tz::Tz;
 
 use sqlx::MySql;
 use sqlx::Transaction;
@@ -56,122 +68,628 @@ pub fn remap_others_schedule(
         .collect()
 }
 
-pub async fn process_room_data(
-    state: &State,
+pub async fn fetch_room<'e, E>(
+    executor: E,
     room_uid: &str,
-    user_uid: &str,
-) -> Result<GetRoomRes, tide::Error> {
-    let room: Room = sqlx::query_as(
+    for_update: bool,
+) -> Result<Room, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = MySql>,
+{
+    let query = format!(
         r#"
         SELECT uid, event_name, schedule_type,
                CAST(dates AS CHAR) as dates,
                CAST(days_of_week AS CHAR) as days_of_week,
-               time_min, time_max, slot_length,
+               start_minute, end_minute, slot_length,
                CAST(schedule AS CHAR) as schedule,
+               CAST(if_needed AS CHAR) as if_needed,
                CAST(participants AS CHAR) as participants,
                timezone,
+               read_only_token,
+               require_approval,
+               CAST(finalized AS CHAR) as finalized,
+               CAST(day_windows AS CHAR) as day_windows,
+               CAST(options AS CHAR) as options,
+               response_deadline,
                expires_at
         FROM rooms
         WHERE uid=?
+        {}
         "#,
-    )
-    .bind(room_uid)
-    .fetch_one(&state.db_pool)
-    .await
-    .map_err(|e| match e {
-        sqlx::Error::RowNotFound => tide::Error::from_str(StatusCode::NotFound, "Room not found"),
-        other => {
-            println!("SQLx error: {:?}", other);
-            tide::Error::from_str(StatusCode::InternalServerError, "Database error")
+        if for_update { "FOR UPDATE" } else { "" }
+    );
+
+    sqlx::query_as(&query)
+        .bind(room_uid)
+        .fetch_one(executor)
+        .await
+}
+
+/// A user of the room as the others list shows them
+pub struct RoomMember {
+    pub user_uid: String,
+    /// Index into the room's participants, `None` for absent-only users
+    pub participant_index: Option<usize>,
+    pub name: String,
+    pub absent_reason: Option<String>,
+    pub is_pending: bool,
+    pub is_required: bool,
+}
+
+/// Everyone in the room in display order: participants (who have a schedule)
+/// first, then any absent-only users not in participants
+pub fn list_members(participants: &[String], users_of_room: &[UserOfRoom]) -> Vec<RoomMember> {
+    let user_info: HashMap<&str, &UserOfRoom> = users_of_room
+        .iter()
+        .map(|user| (user.user_uid.as_str(), user))
+        .collect();
+
+    let mut members: Vec<RoomMember> = participants
+        .iter()
+        .enumerate()
+        .map(|(p_idx, p_uid)| {
+            let user = user_info.get(p_uid.as_str()).copied();
+            RoomMember {
+                user_uid: p_uid.clone(),
+                participant_index: Some(p_idx),
+                name: user.map(|user| user.name.clone()).unwrap_or_default(),
+                absent_reason: user
+                    .filter(|user| user.is_absent)
+                    .map(|user| user.absent_reason.clone()),
+                is_pending: user.map(|user| user.is_pending).unwrap_or(false),
+                is_required: user.map(|user| user.is_required).unwrap_or(false),
+            }
+        })
+        .collect();
+
+    for user in users_of_room {
+        if !participants.contains(&user.user_uid) {
+            members.push(RoomMember {
+                user_uid: user.user_uid.clone(),
+                participant_index: None,
+                name: user.name.clone(),
+                absent_reason: user.is_absent.then(|| user.absent_reason.clone()),
+                is_pending: user.is_pending,
+                is_required: user.is_required,
+            });
         }
-    })?;
+    }
+
+    members
+}
+
+pub fn is_past_deadline(room: &Room) -> bool {
+    room.response_deadline
+        .is_some_and(|deadline| deadline <= time_new::OffsetDateTime::now_utc())
+}
+
+/// Splits everyone but `user_uid` into the others list and the members still
+/// waiting for the owner's approval, who are hidden from the others list
+pub fn split_others(members: Vec<RoomMember>, user_uid: &str) -> (Vec<RoomMember>, Vec<RoomMember>) {
+    members
+        .into_iter()
+        .filter(|member| member.user_uid != user_uid)
+        .partition(|member| !member.is_pending)
+}
+
+pub async fn requires_approval(state: &State, room_uid: &str) -> Result<bool, sqlx::Error> {
+    let (require_approval,): (bool,) =
+        sqlx::query_as("SELECT require_approval FROM rooms WHERE uid=?")
+            .bind(room_uid)
+            .fetch_one(&state.db_pool)
+            .await?;
+
+    Ok(require_approval)
+}
+
+pub fn parse_finalized(room: &Room) -> Result<Option<Finalized>, serde_json::Error> {
+    room.finalized
+        .as_deref()
+        .map(serde_json::from_str)
+        .transpose()
+}
+
+const WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+/// e.g. `Tue May 28 2024, 14:00-15:30` or `Tuesdays, 14:00-15:30`
+pub fn describe_finalized(room: &Room, finalized: &Finalized) -> Option<String> {
+    if room.schedule_type == 3 {
+        return schedule::poll_options_of(room).ok()?.get(finalized.day_index).cloned();
+    }
+    let day = if room.schedule_type == 1 {
+        let days_of_week: Vec<u8> = serde_json::from_str(&room.days_of_week).ok()?;
+        let weekday = WEEKDAYS.get(usize::from(*days_of_week.get(finalized.day_index)?))?;
+        format!("{}s", weekday)
+    } else {
+        let dates: Vec<String> = serde_json::from_str(&room.dates).ok()?;
+        dates.get(finalized.day_index)?.clone()
+    };
+
+    let window = *schedule::column_windows(room).ok()?.get(finalized.day_index)?;
+    Some(format!(
+        "{}, {}-{}",
+        day,
+        format_minute(window.slot_start(finalized.start_slot)),
+        format_minute(window.slot_start(finalized.end_slot))
+    ))
+}
+
+/// The room as `user_uid` sees it, shifted into `viewer_tz` if given
+pub async fn process_room_data(
+    state: &State,
+    room_uid: &str,
+    user_uid: &str,
+    viewer_tz: Option<Tz>,
+) -> Result<GetRoomRes, tide::Error> {
+    let room: Room = fetch_room(&state.db_pool, room_uid, false)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => {
+                tide::Error::from_str(StatusCode::NotFound, "Room not found")
+            }
+            other => {
+                println!("SQLx error: {:?}", other);
+                tide::Error::from_str(StatusCode::InternalServerError, "Database error")
+            }
+        })?;
 
     let participants: Vec<String> = serde_json::from_str(&room.participants)?;
     let schedule: Vec<Vec<Vec<usize>>> = serde_json::from_str(&room.schedule)?;
+    let if_needed = schedule::conform(serde_json::from_str(&room.if_needed)?, &schedule);
     let user_index = participants.iter().position(|p| p == user_uid);
 
-    let (user_schedule, others_schedule) = seperate_users_schedule(schedule, user_index);
+    // DST marks and offsets are worked out on the room's own dates first
+    let room_tz: Tz = room.timezone.parse().unwrap_or(Tz::UTC);
+    let windows = schedule::column_windows(&room)?;
+    let room_dates = schedule::column_dates(&room, Utc::now().with_timezone(&room_tz).date_naive())?;
+    let start_minutes: Vec<u32> = windows.iter().map(|window| window.start_minute).collect();
+    let mut utc_offsets = match room.schedule_type {
+        3 => Vec::new(),
+        _ => schedule::utc_offsets(room_tz, &room_dates, &start_minutes),
+    };
+    let mut dst_slots = schedule::dst_slots(room_tz, &room_dates, &schedule, &windows);
+
+    // Poll options have no times to shift
+    let viewer_tz = viewer_tz.filter(|tz| tz.name() != room.timezone && room.schedule_type != 3);
+    let viewer_layout = match viewer_tz {
+        Some(tz) => {
+            let today = Utc::now().with_timezone(&tz).date_naive();
+            Some((tz, ViewerLayout::new(&room, &schedule, tz, today)?))
+        }
+        None => None,
+    };
+
+    let mut user_preferences = schedule::preferences_of(&schedule, &if_needed, user_index);
+    let (mut user_schedule, others_schedule) = seperate_users_schedule(schedule, user_index);
+    let (_, others_if_needed) = seperate_users_schedule(if_needed, user_index);
 
     // Get all users in the room
-    let users_of_room: Vec<(String, String, bool, String)> = sqlx::query_as(
-        r#"
-        SELECT user_uid, name, is_absent, absent_reason FROM users_of_rooms
-        WHERE room_uid=?
-        "#,
-    )
-    .bind(room_uid)
-    .fetch_all(&state.db_pool)
-    .await?;
+    let users_of_room: Vec<UserOfRoom> =
+        sqlx::query_as("SELECT * FROM users_of_rooms WHERE room_uid=?")
+            .bind(room_uid)
+            .fetch_all(&state.db_pool)
+            .await?;
+
+    // Current user info
+    let current_user = users_of_room.iter().find(|user| user.user_uid == user_uid);
+    let user_name = current_user.map(|user| user.name.clone()).unwrap_or_default();
+    let is_owner = current_user.map(|user| user.is_owner).unwrap_or(false);
+    let is_pending = current_user.map(|user| user.is_pending).unwrap_or(false);
 
-    // Build a lookup from uid → (name, is_owner, is_absent, absent_reason)
-    let user_info: HashMap<&str, (&str, bool, &str)> = users_of_room
+    let mut absent_reasons = vec![current_user
+        .filter(|user| user.is_absent)
+        .map(|user| user.absent_reason.clone())];
+    let mut required = vec![current_user.map(|user| user.is_required).unwrap_or(false)];
+
+    let (others, pending) = split_others(list_members(&participants, &users_of_room), user_uid);
+
+    let participant_to_others: HashMap<usize, usize> = others
         .iter()
-        .map(|(uid, name, is_absent, reason)| (uid.as_str(), (name.as_str(), *is_absent, reason.as_str())))
+        .enumerate()
+        .filter_map(|(o_idx, member)| member.participant_index.map(|p_idx| (p_idx, o_idx)))
         .collect();
 
-    // Current user info
-    let (user_name, is_owner, is_absent, absent_reason): (String, bool, bool, String) =
-        sqlx::query_as(
-            r#"
-            SELECT name, is_owner, is_absent, absent_reason FROM users_of_rooms
-            WHERE room_uid=? AND user_uid=?
-            "#,
-        )
-        .bind(room_uid)
-        .bind(user_uid)
-        .fetch_one(&state.db_pool)
-        .await
-        .unwrap_or((String::new(), false, false, String::new()));
-
-    // Build others list: participants (excluding current user) in order,
-    // then any absent-only users not in participants
-    let mut others_names = Vec::new();
-    let mut absent_reasons = Vec::new();
-    let mut participant_to_others: HashMap<usize, usize> = HashMap::new();
-
-    absent_reasons.push(if is_absent { Some(absent_reason) } else { None });
-
-    for (p_idx, p_uid) in participants.iter().enumerate() {
-        if Some(p_idx) != user_index {
-            participant_to_others.insert(p_idx, others_names.len());
-            let (name, p_absent, reason) = user_info.get(p_uid.as_str()).copied().unwrap_or(("", false, ""));
-            others_names.push(name.to_string());
-            absent_reasons.push(if p_absent { Some(reason.to_string()) } else { None });
-        }
-    }
+    let others_names: Vec<String> = others.iter().map(|member| member.name.clone()).collect();
+    required.extend(others.iter().map(|member| member.is_required));
+    absent_reasons.extend(others.into_iter().map(|member| member.absent_reason));
+
+    // Only the owner gets to see who's waiting
+    let pending_names: Vec<String> = if is_owner {
+        pending.into_iter().map(|member| member.name).collect()
+    } else {
+        Vec::new()
+    };
+
+    let finalized = parse_finalized(&room)?;
+
+    let mut others_schedule_remapped = remap_others_schedule(&others_schedule, &participant_to_others);
+    let mut others_if_needed_remapped =
+        remap_others_schedule(&others_if_needed, &participant_to_others);
+
+    let mut dates: Vec<String> = serde_json::from_str(&room.dates)?;
+    let mut days_of_week: Vec<u8> = serde_json::from_str(&room.days_of_week)?;
+    let mut viewer = None;
 
-    // Append absent-only users not in participants
-    for (uid, name, u_absent, reason) in &users_of_room {
-        if uid != user_uid && !participants.contains(uid) {
-            others_names.push(name.clone());
-            absent_reasons.push(if *u_absent { Some(reason.clone()) } else { None });
+    let day_windows: Option<Vec<DayWindow>> = match &room.day_windows {
+        Some(day_windows) => serde_json::from_str(day_windows)?,
+        None => None,
+    };
+    let mut day_windows: Option<Vec<TimeRange>> = day_windows.map(|day_windows| {
+        day_windows
+            .into_iter()
+            .map(|window| TimeRange::from_minutes(window.start_minute, window.end_minute))
+            .collect()
+    });
+
+    if let Some((tz, layout)) = viewer_layout {
+        if room.schedule_type == 1 {
+            days_of_week = layout
+                .dates
+                .iter()
+                .map(|date| date.weekday().num_days_from_sunday() as u8)
+                .collect();
+        } else {
+            dates = layout
+                .dates
+                .iter()
+                .map(|date| date.format(ROOM_DATE_FORMAT).to_string())
+                .collect();
         }
+        user_schedule = layout.to_viewer(&user_schedule, false);
+        user_preferences = layout.to_viewer(&user_preferences, schedule::UNAVAILABLE);
+        others_schedule_remapped = layout.to_viewer(&others_schedule_remapped, Vec::new());
+        others_if_needed_remapped = layout.to_viewer(&others_if_needed_remapped, Vec::new());
+
+        utc_offsets = schedule::utc_offsets(
+            tz,
+            &layout.dates.iter().copied().map(Some).collect::<Vec<_>>(),
+            &layout.first_minutes(),
+        );
+        dst_slots = layout.dst_slots_to_viewer(&dst_slots);
+        // `has_slot` already tells which of the viewer's rows each column uses
+        day_windows = None;
+
+        viewer = Some(ViewerGrid {
+            timezone: tz.name().to_string(),
+            slot_labels: layout.minutes.iter().map(|&minute| format_minute(minute)).collect(),
+            has_slot: layout.has_slot(),
+        });
     }
 
-    let others_schedule_remapped = remap_others_schedule(&others_schedule, &participant_to_others);
+    let responses_closed = is_past_deadline(&room);
+    let options = schedule::poll_options_of(&room)?;
+
+    // Counted on the dates as shown, which a viewer's zone may have shifted
+    let column_weeks = match room_dates.iter().copied().flatten().min() {
+        Some(first) if room.schedule_type == 2 => {
+            let shown: Vec<_> = dates.iter().map(|date| schedule::parse_room_date(date)).collect();
+            schedule::column_weeks(first, &shown)
+        }
+        _ => Vec::new(),
+    };
 
     Ok(GetRoomRes {
         event_name: room.event_name,
         schedule_type: room.schedule_type,
-        dates: serde_json::from_str(&room.dates)?,
-        days_of_week: serde_json::from_str(&room.days_of_week)?,
-        slot_length: room.slot_length,
+        dates,
+        days_of_week,
+        slot_length: (room.schedule_type != 3).then_some(room.slot_length),
         user_schedule,
+        user_preferences,
         others_schedule: others_schedule_remapped,
+        others_if_needed: others_if_needed_remapped,
         others_names,
         user_name,
-        time_range: TimeRange {
-            from_hour: room.time_min,
-            to_hour: room.time_max,
-        },
+        time_range: (room.schedule_type != 3)
+            .then(|| TimeRange::from_minutes(room.start_minute, room.end_minute)),
+        day_windows,
+        column_weeks,
+        options,
         is_owner,
         absent_reasons,
+        required,
         timezone: room.timezone,
+        read_only: false,
+        read_only_token: if is_owner { room.read_only_token } else { None },
+        require_approval: room.require_approval,
+        is_pending,
+        pending_names,
+        finalized,
+        response_deadline: room.response_deadline.map(format_timestamp),
+        responses_closed,
+        utc_offsets,
+        dst_slots,
+        viewer,
     })
 }
 
+/// Why a user's availability wasn't saved, when nothing went wrong
+#[derive(Debug)]
+pub enum SaveRefused {
+    Banned,
+    Finalized,
+    Closed,
+}
+
+impl std::fmt::Display for SaveRefused {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SaveRefused::Banned => "User is banned from this room",
+            SaveRefused::Finalized => "Room is finalized",
+            SaveRefused::Closed => "Responses are closed",
+        })
+    }
+}
+
+impl std::error::Error for SaveRefused {}
+
+/// Replaces the user's availability, joining them to the room first if they
+/// aren't in it yet. An empty `user_name` falls back to their default name.
+pub async fn save_user_schedule(
+    state: &State,
+    room_uid: &str,
+    user_uid: &str,
+    mut user_name: String,
+    user_schedule: &[Vec<Preference>],
+) -> Result<(), Box<dyn std::error::Error>> {
+    if is_banned(state, room_uid, user_uid).await? {
+        return Err(SaveRefused::Banned.into());
+    }
+
+    let mut transaction = state.db_pool.begin().await?;
+
+    // If user isn't in room add them
+    let user_exists: bool = match sqlx::query(
+        r#"
+        SELECT * FROM users_of_rooms
+        WHERE user_uid=? AND room_uid=?
+        "#,
+    )
+    .bind(user_uid)
+    .bind(room_uid)
+    .fetch_one(&state.db_pool)
+    .await
+    {
+        Ok(_) => true,
+        Err(sqlx::Error::RowNotFound) => false,
+        Err(error) => return Err(format!("Database error: {}", error).into()),
+    };
+
+    let is_pending = !user_exists && requires_approval(state, room_uid).await?;
+
+    if !user_exists {
+        if user_name.is_empty() {
+            let default_name: String =
+                (sqlx::query_as("SELECT default_name FROM users WHERE uid=?")
+                    .bind(user_uid)
+                    .fetch_one(&state.db_pool)
+                    .await
+                    .unwrap_or((String::new(),)))
+                .0;
+
+            user_name = default_name;
+        }
+
+        let _ = sqlx::query!(
+            r#"
+            INSERT INTO users_of_rooms (user_uid, room_uid, name, is_owner, is_absent, absent_reason, is_pending)
+            VALUES (?, ?, ?, ?, ?, ?, ?);
+            "#,
+            user_uid,
+            room_uid,
+            user_name,
+            false,
+            false,
+            "",
+            is_pending
+        )
+        .execute(&mut *transaction)
+        .await?;
+    }
+
+    // get room from DB
+    let room: Room = fetch_room(&mut *transaction, room_uid, true).await?;
+
+    if parse_finalized(&room)?.is_some_and(|finalized| finalized.lock_edits) {
+        return Err(SaveRefused::Finalized.into());
+    }
+    if is_past_deadline(&room) && !is_room_owner(state, room_uid, user_uid).await? {
+        return Err(SaveRefused::Closed.into());
+    }
+
+    let mut participants: Vec<String> = serde_json::from_str(&room.participants)?;
+    let mut schedule: Vec<Vec<Vec<usize>>> = serde_json::from_str(&room.schedule)?;
+    let mut if_needed = schedule::conform(serde_json::from_str(&room.if_needed)?, &schedule);
+
+    // Find or add user's participant index
+    let user_p_index = match participants.iter().position(|p| p == user_uid) {
+        Some(idx) => idx,
+        None => {
+            participants.push(user_uid.to_string());
+            participants.len() - 1
+        }
+    };
+
+    schedule::set_preferences(&mut schedule, &mut if_needed, user_p_index, user_schedule);
+
+    // save schedule and participants
+    let _ = sqlx::query!(
+        r#"
+            UPDATE rooms
+            SET schedule=?, if_needed=?, participants=?
+            WHERE uid=?
+            "#,
+        json!(schedule),
+        json!(if_needed),
+        json!(participants),
+        room_uid,
+    )
+    .execute(&mut *transaction)
+    .await;
+
+    transaction.commit().await?;
+
+    broadcast_schedule(state, room_uid, None).await;
+
+    if is_pending {
+        send_pending_list(state, room_uid).await?;
+    }
+
+    Ok(())
+}
+
+pub async fn viewer_timezone(state: &State, room_uid: &str, user_uid: &str) -> Option<Tz> {
+    state
+        .viewer_timezones
+        .lock()
+        .await
+        .get(&(room_uid.to_string(), user_uid.to_string()))
+        .copied()
+}
+
+pub async fn load_members(
+    state: &State,
+    room_uid: &str,
+) -> Result<Vec<RoomMember>, Box<dyn std::error::Error>> {
+    let room: Room = fetch_room(&state.db_pool, room_uid, false).await?;
+    let participants: Vec<String> = serde_json::from_str(&room.participants)?;
+
+    let users_of_room: Vec<UserOfRoom> =
+        sqlx::query_as("SELECT * FROM users_of_rooms WHERE room_uid=?")
+            .bind(room_uid)
+            .fetch_all(&state.db_pool)
+            .await?;
+
+    Ok(list_members(&participants, &users_of_room))
+}
+
+/// Sends everyone connected, except `except`, the schedule as they see it
+pub async fn broadcast_schedule(state: &State, room_uid: &str, except: Option<&str>) {
+    if let Some(room) = state.rooms.lock().await.get(room_uid) {
+        for (wsc_user_uid, wsc) in room.iter() {
+            if Some(wsc_user_uid.as_str()) == except {
+                continue;
+            }
+            let viewer_tz = viewer_timezone(state, room_uid, wsc_user_uid).await;
+            if let Ok(room_data) = process_room_data(state, room_uid, wsc_user_uid, viewer_tz).await {
+                let _ = wsc
+                    .send_json(&json!({
+                        "messageType": "editSchedule",
+                        "payload": {
+                            "userName": room_data.user_name,
+                            "others": room_data.others_names,
+                            "othersSchedule": room_data.others_schedule,
+                            "othersIfNeeded": room_data.others_if_needed,
+                            "absentReasons": room_data.absent_reasons,
+                            "required": room_data.required
+                        }
+                    }))
+                    .await;
+            }
+        }
+    }
+}
+
+/// Sends the owner the names waiting for approval, in `pending_index` order
+pub async fn send_pending_list(state: &State, room_uid: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let owner: Option<(String,)> =
+        sqlx::query_as("SELECT user_uid FROM users_of_rooms WHERE room_uid=? AND is_owner")
+            .bind(room_uid)
+            .fetch_optional(&state.db_pool)
+            .await?;
+    let Some((owner_uid,)) = owner else {
+        return Ok(());
+    };
+
+    let (_, pending) = split_others(load_members(state, room_uid).await?, &owner_uid);
+    let pending_names: Vec<String> = pending.into_iter().map(|member| member.name).collect();
+
+    if let Some(room) = state.rooms.lock().await.get(room_uid) {
+        if let Some(owner_wsc) = room.get(&owner_uid) {
+            let _ = owner_wsc
+                .send_json(&json!({
+                    "messageType": "pendingParticipants",
+                    "payload": { "pending": pending_names },
+                }))
+                .await;
+        }
+    }
+
+    Ok(())
+}
+
+/// The window `range` covers, if it's within a day and splits evenly into slots
+pub fn checked_window(range: &TimeRange, slot_length: u8) -> Option<TimeWindow> {
+    let start_minute = u32::from(range.start_minute());
+    let end_minute = u32::from(range.end_minute());
+    let window = TimeWindow::new(start_minute, end_minute, u32::from(slot_length));
+    (start_minute < schedule::MINUTES_PER_DAY
+        && end_minute <= schedule::MINUTES_PER_DAY
+        && slot_length > 0
+        && window.length().is_multiple_of(window.slot_length))
+    .then_some(window)
+}
+
+/// Every column's window for a new room, its own from `day_windows` if given
+/// or else `time_range`'s. `None` if any is invalid or a column has none.
+fn new_room_windows(
+    schedule_type: u8,
+    column_count: usize,
+    time_range: &TimeRange,
+    slot_length: u8,
+    day_windows: Option<&[TimeRange]>,
+) -> Option<Vec<TimeWindow>> {
+    // Poll options have no hours
+    if schedule_type == 3 {
+        return Some(vec![TimeWindow::OPTION; column_count]);
+    }
+    let window = checked_window(time_range, slot_length)?;
+    match day_windows {
+        Some(day_windows) if day_windows.len() == column_count => day_windows
+            .iter()
+            .map(|range| checked_window(range, slot_length))
+            .collect(),
+        Some(_) => None,
+        None => Some(vec![window; column_count]),
+    }
+}
+
 pub async fn create_room(mut req: Request<State>) -> tide::Result {
-    let req_body = match req.body_json::<CreateRoomReq>().await {
+    let mut body = match req.body_json::<serde_json::Value>().await {
+        Ok(res) => res,
+        Err(e) => {
+            println!("err: {}", { e });
+            return Ok(Response::new(StatusCode::BadRequest));
+        }
+    };
+
+    // A template fills in whatever the request leaves out
+    if let Some(template_name) = body.get("template").and_then(|name| name.as_str()) {
+        let Some(user_uid) = get_user_uid_from_cookie(&req).await else {
+            return Ok(Response::new(StatusCode::Unauthorized));
+        };
+        let Some(settings) = template::load(&req.state().db_pool, &user_uid, template_name).await?
+        else {
+            return Ok(Response::new(StatusCode::NotFound));
+        };
+        if let (Some(fields), serde_json::Value::Object(settings)) = (body.as_object_mut(), settings)
+        {
+            for (key, value) in settings {
+                fields.entry(key).or_insert(value);
+            }
+        }
+    }
+
+    let req_body = match serde_json::from_value::<CreateRoomReq>(body) {
         Ok(res) => res,
         Err(e) => {
             println!("err: {}", { e });
@@ -179,19 +697,196 @@ pub async fn create_room(mut req: Request<State>) -> tide::Result {
         }
     };
 
+    insert_room(&req, req_body).await
+}
+
+/// Clones the room's settings, dates moved by `days_offset`, into a new room
+/// of the owner's without anyone's responses
+pub async fn clone_room(mut req: Request<State>) -> tide::Result {
+    #[derive(Deserialize)]
+    struct CloneRoomReq {
+        #[serde(default)]
+        days_offset: i64,
+        event_name: Option<String>,
+        challenge: Option<ChallengeSolution>,
+    }
+    let clone_req: CloneRoomReq = match req.body_json().await {
+        Ok(res) => res,
+        Err(_) => return Ok(Response::new(StatusCode::BadRequest)),
+    };
+    let room_uid = req.param("room_uid")?.to_uppercase();
+
+    let Some(user_uid) = get_user_uid_from_cookie(&req).await else {
+        return Ok(Response::new(StatusCode::Unauthorized));
+    };
+    if !is_room_owner(req.state(), &room_uid, &user_uid).await? {
+        return Ok(Response::new(StatusCode::Forbidden));
+    }
+
+    let room = match fetch_room(&req.state().db_pool, &room_uid, false).await {
+        Ok(room) => room,
+        Err(sqlx::Error::RowNotFound) => return Ok(Response::new(StatusCode::NotFound)),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut settings = room_settings(&room, clone_req.days_offset)?;
+    if let Some(event_name) = clone_req.event_name {
+        settings["event_name"] = json!(event_name);
+    }
+    settings["schedule"] = json!([[]]);
+    settings["challenge"] = json!(clone_req.challenge);
+
+    let req_body = match serde_json::from_value::<CreateRoomReq>(settings) {
+        Ok(res) => res,
+        Err(_) => return Ok(Response::new(StatusCode::BadRequest)),
+    };
+
+    insert_room(&req, req_body).await
+}
+
+/// The room's settings in `CreateRoomReq`'s shape, without anyone's schedule.
+/// Dates move by `days_offset`, weekly rooms stay on their weekdays.
+pub fn room_settings(
+    room: &Room,
+    days_offset: i64,
+) -> Result<serde_json::Value, serde_json::Error> {
+    let shifted: Vec<Option<NaiveDate>> = schedule::column_dates(room, Utc::now().date_naive())?
+        .into_iter()
+        .map(|date| Some(date? + chrono::Duration::days(days_offset)))
+        .collect();
+    let format = |date: &NaiveDate| date.format(ROOM_DATE_FORMAT).to_string();
+
+    let dates = match room.schedule_type {
+        1 => json!(serde_json::from_str::<Vec<u8>>(&room.days_of_week)?),
+        3 => json!({ "options": schedule::poll_options_of(room)? }),
+        2 => {
+            let first = shifted.iter().copied().flatten().min();
+            let weeks = first.map(|first| schedule::column_weeks(first, &shifted));
+            let week_count = weeks
+                .iter()
+                .flatten()
+                .flatten()
+                .max()
+                .map_or(1, |&last| last + 1);
+            json!({
+                "start_date": first.as_ref().map(format),
+                "days_of_week": serde_json::from_str::<Vec<u8>>(&room.days_of_week)?,
+                "week_count": week_count,
+            })
+        }
+        _ => json!(shifted.iter().flatten().map(format).collect::<Vec<_>>()),
+    };
+
+    let day_windows: Option<Vec<DayWindow>> = match &room.day_windows {
+        Some(day_windows) => serde_json::from_str(day_windows)?,
+        None => None,
+    };
+
+    let mut settings = json!({
+        "event_name": room.event_name,
+        "schedule_type": room.schedule_type,
+        "dates": dates,
+        "slot_length": room.slot_length,
+        "time_range": TimeRange::from_minutes(room.start_minute, room.end_minute),
+        "day_windows": day_windows.map(|day_windows| {
+            day_windows
+                .into_iter()
+                .map(|window| TimeRange::from_minutes(window.start_minute, window.end_minute))
+                .collect::<Vec<_>>()
+        }),
+        "timezone": room.timezone,
+        "require_approval": room.require_approval,
+    });
+    // Polls have no hours to carry over
+    if let (3, Some(settings)) = (room.schedule_type, settings.as_object_mut()) {
+        for key in ["slot_length", "time_range", "day_windows"] {
+            settings.remove(key);
+        }
+    }
+    Ok(settings)
+}
+
+/// Validates and creates the room with the requesting user as its owner,
+/// signing them up first if needed
+async fn insert_room(
+    req: &Request<State>,
+    mut req_body: CreateRoomReq,
+) -> tide::Result {
+    let mut options: Option<Vec<String>> = None;
+    // Multi-week rooms are kept as the dates they span, and last at least as long
+    let mut expiry_days: i64 = 31;
+    let (schedule_type, dates, days_of_week): (u8, Vec<String>, Vec<u8>) = match req_body.dates {
+        ScheduleDates::Dates(d) => (0, d, Vec::new()),
+        ScheduleDates::DaysOfWeek(d) => (1, Vec::new(), d),
+        ScheduleDates::Weeks {
+            start_date,
+            mut days_of_week,
+            week_count,
+        } => match schedule::week_dates(&start_date, &days_of_week, week_count) {
+            Some(dates) => {
+                expiry_days = expiry_days.max(i64::from(week_count) * 7 + 7);
+                days_of_week.sort();
+                days_of_week.dedup();
+                (2, dates, days_of_week)
+            }
+            None => return Ok(Response::new(StatusCode::BadRequest)),
+        },
+        ScheduleDates::Options { options: choices } => match schedule::poll_options(choices) {
+            // A single slot per option, at no time of day
+            Some(choices) => {
+                options = Some(choices);
+                req_body.time_range = TimeRange::default();
+                req_body.slot_length = 0;
+                req_body.day_windows = None;
+                (3, Vec::new(), Vec::new())
+            }
+            None => return Ok(Response::new(StatusCode::BadRequest)),
+        },
+    };
+    let column_count = match (schedule_type, &options) {
+        (1, _) => days_of_week.len(),
+        (_, Some(options)) => options.len(),
+        _ => dates.len(),
+    };
+    let start_minute = req_body.time_range.start_minute();
+    let end_minute = req_body.time_range.end_minute();
+
+    let windows = new_room_windows(
+        schedule_type,
+        column_count,
+        &req_body.time_range,
+        req_body.slot_length,
+        req_body.day_windows.as_deref(),
+    );
+
     if req_body.event_name.len() > 64
-        || req_body.time_range.from_hour >= 24
-        || req_body.time_range.to_hour > 24
-        || req_body.slot_length == 0
+        || windows.is_none()
         || req_body.schedule.is_empty()
+        || req_body.timezone.parse::<Tz>().is_err()
     {
         return Ok(Response::new(StatusCode::BadRequest));
     }
+    let windows = windows.unwrap_or_default();
+    let day_windows = req_body.day_windows.as_ref().map(|day_windows| {
+        let day_windows: Vec<DayWindow> = day_windows.iter().map(TimeRange::day_window).collect();
+        json!(day_windows)
+    });
+
+    let challenges = &req.state().challenges;
+    if challenges.is_required() {
+        let solved = match &req_body.challenge {
+            Some(solution) => challenges.verify(solution).await,
+            None => false,
+        };
+        if !solved {
+            return Ok(Response::new(StatusCode::Forbidden));
+        }
+    }
 
     let mut transaction: Transaction<'_, MySql> = req.state().db_pool.begin().await?;
 
     let mut response = Response::new(StatusCode::Ok);
-    let mut user_uid: Option<String> = get_user_uid_from_cookie(&req).await;
+    let mut user_uid: Option<String> = get_user_uid_from_cookie(req).await;
 
     if user_uid == None {
         match signup(&mut transaction).await {
@@ -224,49 +919,39 @@ pub async fn create_room(mut req: Request<State>) -> tide::Result {
         };
     }
 
-    let (schedule_type, dates, days_of_week) = match req_body.dates {
-        ScheduleDates::Dates(d) => (0u8, json!(d), serde_json::json!([])),
-        ScheduleDates::DaysOfWeek(d) => (1u8, serde_json::json!([]), json!(d)),
-    };
+    let (dates, days_of_week) = (json!(dates), json!(days_of_week));
 
     let participants = json!([user_uid.clone().unwrap()]);
 
-    let schedule = json!(req_body
-        .schedule
-        .iter()
-        .map(|row| {
-            row.iter()
-                .map(|&avail| {
-                    if avail {
-                        vec![0u32]
-                    } else {
-                        vec![] as Vec<u32>
-                    }
-                })
-                .collect::<Vec<_>>()
-        })
-        .collect::<Vec<_>>());
+    let mut schedule = schedule::empty_grid(&windows);
+    let mut if_needed = schedule.clone();
+    schedule::set_preferences(&mut schedule, &mut if_needed, 0, &req_body.schedule);
+    let (schedule, if_needed) = (json!(schedule), json!(if_needed));
 
     // TODO: Make this the last day of days plus an offset
     let expiry: sqlx::types::time::OffsetDateTime =
-        sqlx::types::time::OffsetDateTime::now_utc() + 31.days();
+        sqlx::types::time::OffsetDateTime::now_utc() + expiry_days.days();
 
     let _ = match sqlx::query!(
         r#"
-        INSERT INTO rooms (uid, event_name, schedule_type, dates, days_of_week, time_min, time_max, slot_length, schedule, participants, timezone, expires_at)
-        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        INSERT INTO rooms (uid, event_name, schedule_type, dates, days_of_week, start_minute, end_minute, day_windows, options, slot_length, schedule, if_needed, participants, timezone, require_approval, expires_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
         "#,
         room_uid,
         req_body.event_name,
         schedule_type,
         dates,
         days_of_week,
-        req_body.time_range.from_hour,
-        req_body.time_range.to_hour,
+        start_minute,
+        end_minute,
+        day_windows,
+        options.map(|options| json!(options)),
         req_body.slot_length,
         schedule,
+        if_needed,
         participants,
         req_body.timezone,
+        req_body.require_approval,
         expiry
     )
     .execute(&mut *transaction)
@@ -312,20 +997,47 @@ pub async fn create_room(mut req: Request<State>) -> tide::Result {
     Ok(response)
 }
 
+pub async fn get_challenge(req: Request<State>) -> tide::Result {
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(json!(req.state().challenges.issue().await));
+    Ok(response)
+}
+
 pub async fn get_room(req: Request<State>) -> tide::Result {
     let room_uid = req.param("room_uid")?.to_uppercase();
     let room_uid = room_uid.as_str();
 
     let mut response = Response::new(StatusCode::Ok);
 
-    let user_uid = get_user_uid_from_cookie(&req)
-        .await
-        .unwrap_or_else(|| String::from("none"));
+    // Read-only viewers are served as an anonymous user, even if they have a cookie
+    let read_only = match get_read_only_token(&req) {
+        Some(token) => {
+            if !is_valid_read_only_token(req.state(), room_uid, &token).await {
+                return Ok(Response::new(StatusCode::Forbidden));
+            }
+            true
+        }
+        None => false,
+    };
+
+    let user_uid = if read_only {
+        String::from("none")
+    } else {
+        get_user_uid_from_cookie(&req)
+            .await
+            .unwrap_or_else(|| String::from("none"))
+    };
 
-    let room_data = match process_room_data(&req.state(), room_uid, &user_uid).await {
+    let viewer_tz = match get_viewer_timezone(&req) {
+        Ok(viewer_tz) => viewer_tz,
+        Err(_) => return Ok(Response::new(StatusCode::BadRequest)),
+    };
+
+    let mut room_data = match process_room_data(&req.state(), room_uid, &user_uid, viewer_tz).await {
         Ok(res) => res,
         Err(_) => return Ok(Response::new(StatusCode::NotFound)),
     };
+    room_data.read_only = read_only;
 
     let response_body_string = serde_json::to_string(&room_data)?;
     response.set_body(response_body_string);
@@ -333,6 +1045,145 @@ pub async fn get_room(req: Request<State>) -> tide::Result {
     Ok(response)
 }
 
+pub async fn best_times(req: Request<State>) -> tide::Result {
+    #[derive(Deserialize)]
+    struct BestTimesQuery {
+        duration: Option<u32>,
+        limit: Option<usize>,
+    }
+    let query: BestTimesQuery = match req.query() {
+        Ok(query) => query,
+        Err(_) => return Ok(Response::new(StatusCode::BadRequest)),
+    };
+    let room_uid = req.param("room_uid")?.to_uppercase();
+
+    let room = match fetch_room(&req.state().db_pool, &room_uid, false).await {
+        Ok(room) => room,
+        Err(sqlx::Error::RowNotFound) => return Ok(Response::new(StatusCode::NotFound)),
+        Err(e) => return Err(e.into()),
+    };
+
+    let windows = schedule::column_windows(&room)?;
+    // A poll option is a single slot however long the meeting runs
+    let (duration, slot_length, run_length) = if room.schedule_type == 3 {
+        (None, None, 1)
+    } else {
+        let slot_length = u32::from(room.slot_length);
+        let duration = query.duration.unwrap_or(slot_length);
+        if duration == 0 || slot_length == 0 {
+            return Ok(Response::new(StatusCode::BadRequest));
+        }
+        // Longer than every day is never going to fit
+        let run_length = duration.div_ceil(slot_length) as usize;
+        if run_length > windows.iter().map(TimeWindow::slot_count).max().unwrap_or(0) {
+            return Ok(Response::new(StatusCode::BadRequest));
+        }
+        (Some(duration), Some(slot_length), run_length)
+    };
+    let limit = query.limit.unwrap_or(10).min(100);
+
+    let participants: Vec<String> = serde_json::from_str(&room.participants)?;
+    let mut schedule: Grid = serde_json::from_str(&room.schedule)?;
+    let mut if_needed = schedule::conform(serde_json::from_str(&room.if_needed)?, &schedule);
+    let dates: Vec<String> = serde_json::from_str(&room.dates)?;
+    let days_of_week: Vec<u8> = serde_json::from_str(&room.days_of_week)?;
+    let options = schedule::poll_options_of(&room)?;
+
+    let users_of_room: Vec<UserOfRoom> =
+        sqlx::query_as("SELECT * FROM users_of_rooms WHERE room_uid=?")
+            .bind(&room_uid)
+            .fetch_all(&req.state().db_pool)
+            .await?;
+
+    // Pending users aren't shown to anyone yet, so they don't count either
+    let members: Vec<RoomMember> = list_members(&participants, &users_of_room)
+        .into_iter()
+        .filter(|member| !member.is_pending)
+        .collect();
+    let counted: Vec<usize> = members
+        .iter()
+        .filter_map(|member| member.participant_index)
+        .collect();
+    for slot in schedule.iter_mut().chain(if_needed.iter_mut()).flatten() {
+        slot.retain(|p_idx| counted.contains(p_idx));
+    }
+
+    // A required member without any availability rules out every slot
+    let required_members: Vec<&RoomMember> =
+        members.iter().filter(|member| member.is_required).collect();
+    let required: Option<Vec<usize>> = required_members
+        .iter()
+        .map(|member| member.participant_index)
+        .collect();
+
+    let candidates: Vec<_> = required
+        .map(|required| schedule::best_times(&schedule, &if_needed, &required, run_length))
+        .unwrap_or_default()
+        .into_iter()
+        .take(limit)
+        .map(|candidate| {
+            let names = |p_indices: &[usize]| -> Vec<&String> {
+                members
+                    .iter()
+                    .filter(|member| {
+                        member
+                            .participant_index
+                            .is_some_and(|p_idx| p_indices.contains(&p_idx))
+                    })
+                    .map(|member| &member.name)
+                    .collect()
+            };
+            let fully_available: Vec<usize> = candidate
+                .available
+                .iter()
+                .copied()
+                .filter(|p_idx| !candidate.if_needed.contains(p_idx))
+                .collect();
+            let missing: Vec<&String> = members
+                .iter()
+                .filter(|member| {
+                    !member
+                        .participant_index
+                        .is_some_and(|p_idx| candidate.available.contains(&p_idx))
+                })
+                .map(|member| &member.name)
+                .collect();
+            // Poll options have no times
+            let time_of = |slot: usize| {
+                windows
+                    .get(candidate.day_index)
+                    .filter(|window| **window != TimeWindow::OPTION)
+                    .map(|window| format_minute(window.slot_start(slot)))
+            };
+
+            json!({
+                "day_index": candidate.day_index,
+                "date": dates.get(candidate.day_index),
+                "day_of_week": days_of_week.get(candidate.day_index),
+                "option": options.get(candidate.day_index),
+                "start_slot": candidate.start_slot,
+                "end_slot": candidate.end_slot,
+                "start_time": time_of(candidate.start_slot),
+                "end_time": time_of(candidate.end_slot),
+                "available_count": fully_available.len(),
+                "if_needed_count": candidate.if_needed.len(),
+                "available": names(&fully_available),
+                "if_needed": names(&candidate.if_needed),
+                "missing": missing,
+            })
+        })
+        .collect();
+
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(json!({
+        "duration": duration,
+        "slot_length": slot_length,
+        "required": required_members.iter().map(|member| &member.name).collect::<Vec<_>>(),
+        "candidates": candidates,
+    }));
+    Ok(response)
+}
+
 pub async fn delete_room(req: Request<State>) -> tide::Result {
     let response = Response::new(StatusCode::Ok);
 
@@ -346,20 +1197,33 @@ pub async fn delete_room(req: Request<State>) -> tide::Result {
 
     let user_uid: &str = user_uid.as_ref();
 
-    let mut transaction: Transaction<'_, MySql> = req.state().db_pool.begin().await?;
-
     let (is_owner,): (bool,) =
         sqlx::query_as("SELECT is_owner FROM users_of_rooms WHERE user_uid=? AND room_uid=?")
             .bind(user_uid)
             .bind(room_uid)
-            .fetch_one(&mut *transaction)
+            .fetch_one(&req.state().db_pool)
             .await?;
 
     if !is_owner {
         return Ok(Response::new(StatusCode::Forbidden));
     }
 
-    match sqlx::query(
+    if remove_room(req.state(), room_uid, Some(user_uid)).await.is_err() {
+        return Ok(Response::new(StatusCode::InternalServerError));
+    }
+
+    Ok(response)
+}
+
+/// Deletes the room and its users, then pings everyone connected except `deleted_by`
+pub async fn remove_room(
+    state: &State,
+    room_uid: &str,
+    deleted_by: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let mut transaction: Transaction<'_, MySql> = state.db_pool.begin().await?;
+
+    sqlx::query(
         r#"
         DELETE FROM rooms
         WHERE uid=?
@@ -367,26 +1231,23 @@ pub async fn delete_room(req: Request<State>) -> tide::Result {
     )
     .bind(room_uid)
     .execute(&mut *transaction)
-    .await
-    {
-        Ok(_) => {}
-        Err(_) => return Ok(Response::new(StatusCode::InternalServerError)),
-    };
+    .await?;
 
-    match sqlx::query("DELETE FROM users_of_rooms WHERE room_uid=?")
+    sqlx::query("DELETE FROM users_of_rooms WHERE room_uid=?")
         .bind(room_uid)
         .execute(&mut *transaction)
-        .await
-    {
-        Ok(_) => {}
-        Err(_) => return Ok(Response::new(StatusCode::InternalServerError)),
-    };
+        .await?;
+
+    sqlx::query("DELETE FROM room_bans WHERE room_uid=?")
+        .bind(room_uid)
+        .execute(&mut *transaction)
+        .await?;
 
     transaction.commit().await?;
 
-    if let Some(room) = req.state().rooms.lock().await.get(room_uid) {
+    if let Some(room) = state.rooms.lock().await.get(room_uid) {
         for (this_user_uid, user_wsc) in room.iter() {
-            if *this_user_uid != user_uid {
+            if Some(this_user_uid.as_str()) != deleted_by {
                 let _ = user_wsc
                     .send_json(&RoomDeletedPing {
                         message_type: "roomDeleted".to_string(),
@@ -396,42 +1257,164 @@ pub async fn delete_room(req: Request<State>) -> tide::Result {
         }
     }
 
+    Ok(())
+}
+
+pub async fn is_valid_read_only_token(state: &State, room_uid: &str, token: &str) -> bool {
+    match sqlx::query_as::<_, (Option<String>,)>("SELECT read_only_token FROM rooms WHERE uid=?")
+        .bind(room_uid)
+        .fetch_one(&state.db_pool)
+        .await
+    {
+        Ok((Some(room_token),)) => room_token == token,
+        _ => false,
+    }
+}
+
+pub async fn is_room_owner(state: &State, room_uid: &str, user_uid: &str) -> Result<bool, sqlx::Error> {
+    match sqlx::query_as::<_, (bool,)>(
+        "SELECT is_owner FROM users_of_rooms WHERE user_uid=? AND room_uid=?",
+    )
+    .bind(user_uid)
+    .bind(room_uid)
+    .fetch_one(&state.db_pool)
+    .await
+    {
+        Ok((is_owner,)) => Ok(is_owner),
+        Err(sqlx::Error::RowNotFound) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn is_banned(state: &State, room_uid: &str, user_uid: &str) -> Result<bool, sqlx::Error> {
+    let ban: Option<(u32,)> =
+        sqlx::query_as("SELECT id FROM room_bans WHERE room_uid=? AND user_uid=?")
+            .bind(room_uid)
+            .bind(user_uid)
+            .fetch_optional(&state.db_pool)
+            .await?;
+
+    Ok(ban.is_some())
+}
+
+pub async fn create_read_only_token(req: Request<State>) -> tide::Result {
+    let room_uid = req.param("room_uid")?.to_uppercase();
+
+    let Some(user_uid) = get_user_uid_from_cookie(&req).await else {
+        return Ok(Response::new(StatusCode::Unauthorized));
+    };
+
+    if !is_room_owner(req.state(), &room_uid, &user_uid).await? {
+        return Ok(Response::new(StatusCode::Forbidden));
+    }
+
+    // Creating a new token replaces (and so invalidates) any previous one
+    let read_only_token = generate_auth_token();
+
+    sqlx::query("UPDATE rooms SET read_only_token=? WHERE uid=?")
+        .bind(&read_only_token)
+        .bind(&room_uid)
+        .execute(&req.state().db_pool)
+        .await?;
+
+    disconnect_read_only_viewers(req.state(), &room_uid).await;
+
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(json!({
+        "read_only_token": read_only_token
+    }));
     Ok(response)
 }
 
+pub async fn delete_read_only_token(req: Request<State>) -> tide::Result {
+    let room_uid = req.param("room_uid")?.to_uppercase();
+
+    let Some(user_uid) = get_user_uid_from_cookie(&req).await else {
+        return Ok(Response::new(StatusCode::Unauthorized));
+    };
+
+    if !is_room_owner(req.state(), &room_uid, &user_uid).await? {
+        return Ok(Response::new(StatusCode::Forbidden));
+    }
+
+    sqlx::query("UPDATE rooms SET read_only_token=NULL WHERE uid=?")
+        .bind(&room_uid)
+        .execute(&req.state().db_pool)
+        .await?;
+
+    disconnect_read_only_viewers(req.state(), &room_uid).await;
+
+    Ok(Response::new(StatusCode::Ok))
+}
+
+async fn disconnect_read_only_viewers(state: &State, room_uid: &str) {
+    if let Some(room) = state.rooms.lock().await.get(room_uid) {
+        for (this_user_uid, user_wsc) in room.iter() {
+            if this_user_uid.starts_with(READ_ONLY_VIEWER_PREFIX) {
+                let _ = user_wsc
+                    .send(tide_websockets::Message::Close(None))
+                    .await;
+            }
+        }
+    }
+}
+
+/// e.g. `May 28 2024 14:00`
+fn format_deadline(deadline: time_new::OffsetDateTime) -> String {
+    chrono::DateTime::from_timestamp(deadline.unix_timestamp(), 0)
+        .map(|deadline| deadline.format("%b %d %Y %H:%M").to_string())
+        .unwrap_or_default()
+}
+
 pub async fn og_page(req: Request<State>) -> tide::Result {
     let room_uid = req.param("room_uid")?.to_uppercase();
     let frontend_url = std::env::var("FRONTEND_URL").unwrap_or_else(|_| "https://cmon.rsvp".to_string());
 
-    let room_info: Option<(String,)> = sqlx::query_as(
-        "SELECT event_name FROM rooms WHERE uid=?"
-    )
-    .bind(&room_uid)
-    .fetch_optional(&req.state().db_pool)
-    .await?;
+    let room_info = match fetch_room(&req.state().db_pool, &room_uid, false).await {
+        Ok(room) => Some(room),
+        Err(sqlx::Error::RowNotFound) => None,
+        Err(e) => return Err(e.into()),
+    };
 
     let participant_count: Option<(i64,)> = sqlx::query_as(
-        "SELECT COUNT(*) FROM users_of_rooms WHERE room_uid=?"
+        "SELECT COUNT(*) FROM users_of_rooms WHERE room_uid=? AND NOT is_pending"
     )
     .bind(&room_uid)
     .fetch_optional(&req.state().db_pool)
     .await?;
 
     let (title, description) = match room_info {
-        Some((event_name,)) => {
+        Some(room) => {
             let count = participant_count.map(|(c,)| c).unwrap_or(0);
-            let desc = if count > 0 {
+            let finalized = parse_finalized(&room)
+                .ok()
+                .flatten()
+                .and_then(|finalized| describe_finalized(&room, &finalized));
+            let desc = if let Some(finalized) = finalized {
+                format!("It's set for {}.", finalized)
+            } else if is_past_deadline(&room) {
+                "Responses are closed.".to_string()
+            } else if let Some(deadline) = room.response_deadline {
+                format!(
+                    "{} responded so far, responses close {} UTC.",
+                    count,
+                    format_deadline(deadline)
+                )
+            } else if count > 0 {
                 format!("{} {} responded. Add your availability.", count, if count == 1 { "person has" } else { "people have" })
             } else {
                 "Be the first to add your availability.".to_string()
             };
-            (format!("Join '{}' on cmon.rsvp", event_name), desc)
+            (format!("Join '{}' on cmon.rsvp", room.event_name), desc)
         }
         None => (
             "cmon.rsvp".to_string(),
             "This room may have expired or been deleted.".to_string(),
         ),
     };
+    // Event names and poll options are whatever the room's owner typed
+    let (title, description) = (escape_html(&title), escape_html(&description));
+    let (frontend_url, room_uid) = (escape_html(&frontend_url), escape_html(&room_uid));
 
     let html = format!(
         r##"<!doctype html>
@@ -462,3 +1445,147 @@ pub async fn og_page(req: Request<State>) -> tide::Result {
     response.set_body(html);
     Ok(response)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_create_the_same_room() {
+        let weeks = schedule::week_dates("Mon Mar 02 2026", &[1, 3], 2).unwrap();
+        let rooms = [
+            Room {
+                dates: json!(["Mon Mar 02 2026", "Wed Mar 04 2026"]).to_string(),
+                ..Room::for_test()
+            },
+            Room {
+                schedule_type: 1,
+                days_of_week: json!([1, 3]).to_string(),
+                ..Room::for_test()
+            },
+            Room {
+                schedule_type: 2,
+                dates: json!(weeks).to_string(),
+                days_of_week: json!([1, 3]).to_string(),
+                ..Room::for_test()
+            },
+            Room {
+                schedule_type: 3,
+                options: Some(json!(["Pizza", "Sushi"]).to_string()),
+                ..Room::for_test()
+            },
+        ];
+
+        let parsed = |json: &str| serde_json::from_str::<serde_json::Value>(json).unwrap();
+        for room in rooms {
+            let mut settings = room_settings(&room, 0).unwrap();
+            settings["schedule"] = json!([[]]);
+            let req: CreateRoomReq = serde_json::from_value(settings).unwrap();
+
+            assert_eq!(req.schedule_type, room.schedule_type);
+            let same_dates = match req.dates {
+                ScheduleDates::Dates(dates) => json!(dates) == parsed(&room.dates),
+                ScheduleDates::DaysOfWeek(days) => json!(days) == parsed(&room.days_of_week),
+                ScheduleDates::Weeks {
+                    start_date,
+                    days_of_week,
+                    week_count,
+                } => {
+                    schedule::week_dates(&start_date, &days_of_week, week_count).as_ref()
+                        == Some(&weeks)
+                }
+                ScheduleDates::Options { options } => {
+                    Some(json!(options)) == room.options.as_deref().map(parsed)
+                }
+            };
+            assert!(same_dates, "schedule type {}", room.schedule_type);
+            assert_eq!(req.event_name, room.event_name);
+            assert_eq!(req.timezone, room.timezone);
+        }
+    }
+
+    #[test]
+    fn settings_move_dates_by_the_offset() {
+        let room = Room {
+            dates: json!(["Mon Mar 02 2026", "Wed Mar 04 2026"]).to_string(),
+            ..Room::for_test()
+        };
+        let settings = room_settings(&room, 7).unwrap();
+        assert_eq!(settings["dates"], json!(["Mon Mar 09 2026", "Wed Mar 11 2026"]));
+        assert_eq!(settings["slot_length"], json!(60));
+        assert_eq!(settings["time_range"]["from_minute"], json!(9 * 60));
+    }
+
+    fn spans(windows: &[TimeWindow]) -> Vec<(u32, u32, usize)> {
+        windows
+            .iter()
+            .map(|window| (window.start_minute, window.end_minute, window.slot_count()))
+            .collect()
+    }
+
+    #[test]
+    fn windows_must_fit_a_day_and_split_into_slots() {
+        let window = checked_window(&TimeRange::from_minutes(540, 630), 30);
+        assert_eq!(window.map(|window| spans(&[window])), Some(vec![(540, 630, 3)]));
+
+        assert!(checked_window(&TimeRange::from_minutes(540, 630), 60).is_none());
+        assert!(checked_window(&TimeRange::from_minutes(540, 630), 0).is_none());
+        assert!(checked_window(&TimeRange::from_minutes(1380, 1500), 60).is_none());
+    }
+
+    #[test]
+    fn new_rooms_size_each_column_from_its_own_window() {
+        let room_hours = TimeRange::from_minutes(540, 1020);
+        let day_windows = [TimeRange::from_minutes(540, 720), TimeRange::from_minutes(780, 1080)];
+
+        let windows = new_room_windows(0, 2, &room_hours, 60, Some(&day_windows)).unwrap();
+        assert_eq!(spans(&windows), vec![(540, 720, 3), (780, 1080, 5)]);
+        let schedule = schedule::empty_grid(&windows);
+        assert_eq!(schedule.iter().map(Vec::len).collect::<Vec<_>>(), vec![3, 5]);
+
+        let windows = new_room_windows(1, 2, &room_hours, 60, None).unwrap();
+        assert_eq!(spans(&windows), vec![(540, 1020, 8); 2]);
+
+        // Hours a poll is sent with don't matter
+        let windows = new_room_windows(3, 2, &TimeRange::default(), 0, Some(&day_windows));
+        assert!(windows.is_some_and(|windows| windows == vec![TimeWindow::OPTION; 2]));
+    }
+
+    #[test]
+    fn new_rooms_refuse_missing_or_uneven_windows() {
+        let room_hours = TimeRange::from_minutes(540, 1020);
+        let day_windows = [TimeRange::from_minutes(540, 720)];
+        assert!(new_room_windows(0, 2, &room_hours, 60, Some(&day_windows)).is_none());
+
+        let uneven = [TimeRange::from_minutes(540, 720), TimeRange::from_minutes(780, 810)];
+        assert!(new_room_windows(0, 2, &room_hours, 60, Some(&uneven)).is_none());
+        assert!(new_room_windows(0, 2, &TimeRange::from_minutes(540, 570), 60, None).is_none());
+    }
+
+    #[test]
+    fn column_windows_fall_back_to_the_room_window() {
+        let room = Room {
+            dates: json!(["Mon Mar 02 2026", "Wed Mar 04 2026"]).to_string(),
+            ..Room::for_test()
+        };
+        assert_eq!(
+            spans(&schedule::column_windows(&room).unwrap()),
+            vec![(540, 1020, 8); 2]
+        );
+
+        let room = Room {
+            day_windows: Some(
+                json!([
+                    { "start_minute": 540, "end_minute": 720 },
+                    { "start_minute": 780, "end_minute": 1080 },
+                ])
+                .to_string(),
+            ),
+            ..room
+        };
+        assert_eq!(
+            spans(&schedule::column_windows(&room).unwrap()),
+            vec![(540, 720, 3), (780, 1080, 5)]
+        );
+    }
+}
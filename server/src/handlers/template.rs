@@ -0,0 +1,149 @@
+use crate::models::State;
+use crate::room::{fetch_room, is_room_owner, room_settings};
+use crate::utils::{format_timestamp, get_user_uid_from_cookie};
+
+use serde::Deserialize;
+use sqlx::MySqlPool;
+use tide::prelude::*;
+use tide::Request;
+use tide::Response;
+use tide::StatusCode;
+
+const MAX_TEMPLATES: i64 = 50;
+
+/// The template's settings, in `CreateRoomReq`'s shape
+pub async fn load(
+    pool: &MySqlPool,
+    user_uid: &str,
+    name: &str,
+) -> Result<Option<serde_json::Value>, tide::Error> {
+    let row: Option<(String,)> = sqlx::query_as(
+        "SELECT CAST(settings AS CHAR) FROM room_templates WHERE user_uid=? AND name=?",
+    )
+    .bind(user_uid)
+    .bind(name)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(match row {
+        Some((settings,)) => Some(serde_json::from_str(&settings)?),
+        None => None,
+    })
+}
+
+pub async fn list_templates(req: Request<State>) -> tide::Result {
+    let Some(user_uid) = get_user_uid_from_cookie(&req).await else {
+        return Ok(Response::new(StatusCode::Unauthorized));
+    };
+
+    let rows: Vec<(String, String, time_new::OffsetDateTime)> = sqlx::query_as(
+        r#"
+        SELECT name, CAST(settings AS CHAR), created_at FROM room_templates
+        WHERE user_uid=?
+        ORDER BY name
+        "#,
+    )
+    .bind(&user_uid)
+    .fetch_all(&req.state().db_pool)
+    .await?;
+
+    let mut templates = Vec::with_capacity(rows.len());
+    for (name, settings, created_at) in rows {
+        templates.push(json!({
+            "name": name,
+            "settings": serde_json::from_str::<serde_json::Value>(&settings)?,
+            "created_at": format_timestamp(created_at),
+        }));
+    }
+
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(json!({ "templates": templates }));
+    Ok(response)
+}
+
+/// Saves one of the user's rooms as a template, replacing any of the same name
+pub async fn save_template(mut req: Request<State>) -> tide::Result {
+    #[derive(Deserialize)]
+    struct SaveTemplateReq {
+        name: String,
+        room_uid: String,
+    }
+    let save_req: SaveTemplateReq = match req.body_json().await {
+        Ok(res) => res,
+        Err(_) => return Ok(Response::new(StatusCode::BadRequest)),
+    };
+    if save_req.name.is_empty() || save_req.name.len() > 64 {
+        return Ok(Response::new(StatusCode::BadRequest));
+    }
+    let room_uid = save_req.room_uid.to_uppercase();
+
+    let Some(user_uid) = get_user_uid_from_cookie(&req).await else {
+        return Ok(Response::new(StatusCode::Unauthorized));
+    };
+    if !is_room_owner(req.state(), &room_uid, &user_uid).await? {
+        return Ok(Response::new(StatusCode::Forbidden));
+    }
+
+    let room = match fetch_room(&req.state().db_pool, &room_uid, false).await {
+        Ok(room) => room,
+        Err(sqlx::Error::RowNotFound) => return Ok(Response::new(StatusCode::NotFound)),
+        Err(e) => return Err(e.into()),
+    };
+    let settings = room_settings(&room, 0)?;
+
+    let (template_count,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM room_templates WHERE user_uid=? AND name<>?",
+    )
+    .bind(&user_uid)
+    .bind(&save_req.name)
+    .fetch_one(&req.state().db_pool)
+    .await?;
+    if template_count >= MAX_TEMPLATES {
+        return Ok(Response::new(StatusCode::Conflict));
+    }
+
+    sqlx::query(
+        r#"
+        INSERT INTO room_templates (user_uid, name, settings)
+        VALUES (?, ?, ?)
+        ON DUPLICATE KEY UPDATE settings=VALUES(settings), created_at=CURRENT_TIMESTAMP
+        "#,
+    )
+    .bind(&user_uid)
+    .bind(&save_req.name)
+    .bind(&settings)
+    .execute(&req.state().db_pool)
+    .await?;
+
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(json!({ "name": save_req.name, "settings": settings }));
+    Ok(response)
+}
+
+/// Takes the name as `?name=`, which unlike a path segment gets decoded
+pub async fn delete_template(req: Request<State>) -> tide::Result {
+    #[derive(Deserialize)]
+    struct DeleteTemplateQuery {
+        name: String,
+    }
+    let DeleteTemplateQuery { name } = match req.query() {
+        Ok(query) => query,
+        Err(_) => return Ok(Response::new(StatusCode::BadRequest)),
+    };
+
+    let Some(user_uid) = get_user_uid_from_cookie(&req).await else {
+        return Ok(Response::new(StatusCode::Unauthorized));
+    };
+
+    let result = sqlx::query("DELETE FROM room_templates WHERE user_uid=? AND name=?")
+        .bind(&user_uid)
+        .bind(&name)
+        .execute(&req.state().db_pool)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Ok(Response::new(StatusCode::NotFound));
+    }
+
+    Ok(Response::new(StatusCode::Ok))
+}
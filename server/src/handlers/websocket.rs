@@ -1,28 +1,71 @@
 use std::time::{Duration, Instant};
 
-use crate::models::{Room, State, UserOfRoom, WSMessage};
-use crate::room::process_room_data;
-use crate::utils::get_user_uid_from_cookie;
+use crate::models::{
+    DayWindow, Finalized, Room, ScheduleDates, State, TimeRange, UserOfRoom, WSMessage,
+    READ_ONLY_VIEWER_PREFIX,
+};
+use crate::room::{
+    broadcast_schedule, checked_window, fetch_room, is_banned, is_past_deadline, is_room_owner,
+    is_valid_read_only_token, load_members, parse_finalized, process_room_data, requires_approval,
+    save_user_schedule, send_pending_list, split_others, viewer_timezone,
+};
+use crate::schedule::{self, MergeRule, Preference, SlotLayout, ViewerLayout};
+use crate::utils::{
+    format_timestamp, get_read_only_token, get_user_uid_from_cookie, get_viewer_timezone,
+    user_has_live_token,
+};
 
+use chrono::Utc;
+use chrono_tz::Tz;
+use time_new::format_description::well_known::Rfc3339;
 use async_std::prelude::*;
 use futures::select;
 use futures::FutureExt;
 use serde::Deserialize;
 use tide::prelude::*;
 use tide_websockets::WebSocketConnection;
+use uuid::Uuid;
 
 pub async fn connect_websocket(
     req: tide::Request<State>,
     mut wsc: WebSocketConnection,
 ) -> tide::Result<()> {
-    let Some(user_uid) = get_user_uid_from_cookie(&req).await else {
-        let _ = wsc.send(tide_websockets::Message::Close(None)).await;
-        return Ok(());
-    };
     let room_uid = req.param("room_uid")?;
 
+    // A read-only token gets live broadcasts but can never send mutations
+    let read_only_token = get_read_only_token(&req);
+    if let Some(token) = &read_only_token {
+        if !is_valid_read_only_token(req.state(), room_uid, token).await {
+            let _ = wsc.send(tide_websockets::Message::Close(None)).await;
+            return Ok(());
+        }
+    }
+
+    let user_uid = if read_only_token.is_some() {
+        format!("{}{}", READ_ONLY_VIEWER_PREFIX, Uuid::new_v4())
+    } else {
+        let Some(user_uid) = get_user_uid_from_cookie(&req).await else {
+            let _ = wsc.send(tide_websockets::Message::Close(None)).await;
+            return Ok(());
+        };
+        if is_banned(req.state(), room_uid, &user_uid).await? {
+            let _ = wsc.send(tide_websockets::Message::Close(None)).await;
+            return Ok(());
+        }
+        user_uid
+    };
+
     let state = req.state().clone();
 
+    // Unknown zones fall back to room time
+    if let Ok(Some(viewer_tz)) = get_viewer_timezone(&req) {
+        state
+            .viewer_timezones
+            .lock()
+            .await
+            .insert((room_uid.to_string(), user_uid.clone()), viewer_tz);
+    }
+
     // Add connection
     state
         .rooms
@@ -44,6 +87,14 @@ pub async fn connect_websocket(
                     println!("Client failed to respond to ping, closing connection.");
                     break;
                 }
+                // Drop the socket once its token is revoked or expires
+                let still_authorized = match &read_only_token {
+                    Some(token) => is_valid_read_only_token(&state, room_uid, token).await,
+                    None => user_has_live_token(&state, &user_uid).await.unwrap_or(true),
+                };
+                if !still_authorized {
+                    break;
+                }
                 // if let Err(_) = wsc.send(tide_websockets::Message::Ping(vec![])).await {
                 if let Err(_) = wsc.send(tide_websockets::Message::Text("ping".to_string())).await {
                     break;
@@ -73,6 +124,10 @@ pub async fn connect_websocket(
                                     continue;
                                 }
                             };
+                            if read_only_token.is_some() {
+                                println!("Refusing {} from read-only connection", message.message_type);
+                                continue;
+                            }
                             if let Err(e) = handle_websocket_message(
                                 req.state().clone(),
                                 room_uid.to_string(),
@@ -122,6 +177,11 @@ pub async fn connect_websocket(
             }
         }
     }
+    state
+        .viewer_timezones
+        .lock()
+        .await
+        .remove(&(room_uid.to_string(), user_uid.clone()));
 
     Ok(())
 }
@@ -135,133 +195,19 @@ pub async fn handle_websocket_message(
 
     match msg.message_type.as_str() {
         "editSchedule" => {
-            let mut transaction = (&state.db_pool).begin().await?;
-
-            let user_schedule: Vec<Vec<bool>> =
+            // Cells are a preference level, or a bool from older clients
+            let mut user_schedule: Vec<Vec<Preference>> =
                 serde_json::from_value(msg.payload["user_schedule"].clone())?;
 
-            let mut user_name: String = serde_json::from_value(msg.payload["user_name"].clone())?;
-
-            // If user isn't in room add them
-            let user_exists: bool = match sqlx::query(
-                r#"
-                SELECT * FROM users_of_rooms
-                WHERE user_uid=? AND room_uid=?
-                "#,
-            )
-            .bind(&user_uid)
-            .bind(&room_uid)
-            .fetch_one(&state.db_pool)
-            .await
-            {
-                Ok(_) => true,
-                Err(sqlx::Error::RowNotFound) => false,
-                Err(error) => return Err(format!("Database error: {}", error).into()),
-            };
-
-            if !user_exists {
-                if user_name.is_empty() {
-                    let default_name: String =
-                        (sqlx::query_as("SELECT default_name FROM users WHERE uid=?")
-                            .bind(user_uid.clone())
-                            .fetch_one(&state.db_pool)
-                            .await
-                            .unwrap_or((String::new(),)))
-                        .0;
-
-                    user_name = default_name;
-                }
-
-                let _ = sqlx::query!(
-                    r#"
-                    INSERT INTO users_of_rooms (user_uid, room_uid, name, is_owner, is_absent, absent_reason)
-                    VALUES (?, ?, ?, ?, ?, ?);
-                    "#,
-                    user_uid,
-                    room_uid,
-                    user_name,
-                    false,
-                    false,
-                    ""
-                )
-                .execute(&mut *transaction)
-                .await?;
-            }
-
-            // get room from DB
-            let room: Room = sqlx::query_as(
-                r#"
-                    SELECT uid, event_name, schedule_type,
-                           CAST(dates AS CHAR) as dates,
-                           CAST(days_of_week AS CHAR) as days_of_week,
-                           time_min, time_max, slot_length,
-                           CAST(schedule AS CHAR) as schedule,
-                           CAST(participants AS CHAR) as participants,
-                           timezone,
-                           expires_at
-                    FROM rooms
-                    WHERE uid=?
-                    FOR UPDATE
-                "#,
-            )
-            .bind(&room_uid)
-            .fetch_one(&mut *transaction)
-            .await?;
-
-            let mut participants: Vec<String> = serde_json::from_str(&room.participants)?;
-            let mut schedule: Vec<Vec<Vec<usize>>> = serde_json::from_str(&room.schedule)?;
-
-            // Find or add user's participant index
-            let user_p_index = match participants.iter().position(|p| p == &user_uid) {
-                Some(idx) => idx,
-                None => {
-                    participants.push(user_uid.clone());
-                    participants.len() - 1
-                }
-            };
+            let user_name: String = serde_json::from_value(msg.payload["user_name"].clone())?;
 
-            // Remove user's index from all cells, re-add where selected
-            for (i, row) in schedule.iter_mut().enumerate() {
-                for (j, slot) in row.iter_mut().enumerate() {
-                    slot.retain(|&idx| idx != user_p_index);
-                    if i < user_schedule.len() && j < user_schedule[i].len() && user_schedule[i][j] {
-                        slot.push(user_p_index);
-                    }
-                }
+            // Edits come back in the shape the connection is sent, see `ViewerGrid`
+            if let Some(viewer_tz) = viewer_timezone(&state, &room_uid, &user_uid).await {
+                user_schedule =
+                    to_room_time(&state, &room_uid, &user_uid, viewer_tz, &user_schedule).await?;
             }
 
-            // save schedule and participants
-            let _ = sqlx::query!(
-                r#"
-                    UPDATE rooms
-                    SET schedule=?, participants=?
-                    WHERE uid=?
-                    "#,
-                json!(schedule),
-                json!(participants),
-                room_uid,
-            )
-            .execute(&mut *transaction)
-            .await;
-
-            transaction.commit().await?;
-
-            if let Some(room) = state.rooms.lock().await.get(&room_uid) {
-                for (wsc_user_uid, wsc) in room.iter() {
-                    if let Ok(room_data) = process_room_data(&state, &room_uid, &wsc_user_uid).await {
-                        let _ = wsc
-                            .send_json(&json!({
-                            "messageType": "editSchedule",
-                            "payload": {
-                                "userName": room_data.user_name,
-                                "others": room_data.others_names,
-                                "othersSchedule": room_data.others_schedule,
-                                "absentReasons": room_data.absent_reasons
-                            }}))
-                            .await;
-                    }
-                }
-            }
+            save_user_schedule(&state, &room_uid, &user_uid, user_name, &user_schedule).await?;
         }
         "editEventName" => {
             #[derive(Serialize, Deserialize)]
@@ -318,6 +264,8 @@ pub async fn handle_websocket_message(
             let user_name_payload: EditUserNamePayload =
                 serde_json::from_value(msg.payload)?;
 
+            ensure_open(&state, &room_uid, &user_uid).await?;
+
             sqlx::query(
                 r#"
                 UPDATE users_of_rooms
@@ -343,40 +291,33 @@ pub async fn handle_websocket_message(
             .execute(&state.db_pool)
             .await?;
 
-            let users_of_rooms = sqlx::query_as::<_, UserOfRoom>(
-                r#"
-                SELECT * FROM users_of_rooms
-                WHERE room_uid=?
-                "#,
-            )
-            .bind(&room_uid)
-            .fetch_all(&state.db_pool)
-            .await?;
-
             if let Some(room) = state.rooms.lock().await.get(&room_uid) {
                 for (this_user_uid, user_wsc) in room.iter() {
                     if *this_user_uid != user_uid {
-                        let others_names: Vec<String> = users_of_rooms.clone().into_iter().fold(
-                            Vec::new(),
-                            |mut others_names, user| {
-                                if user.user_uid != *this_user_uid {
-                                    others_names.push(user.name);
-                                }
-                                others_names
-                            },
-                        );
-
-                        let _ = user_wsc
-                            .send_json(&json!({
-                                "messageType": "editUserName",
-                                "payload": { "others": others_names},
-                            }))
-                            .await;
+                        let viewer_tz = viewer_timezone(&state, &room_uid, this_user_uid).await;
+                        if let Ok(room_data) =
+                            process_room_data(&state, &room_uid, this_user_uid, viewer_tz).await
+                        {
+                            let _ = user_wsc
+                                .send_json(&json!({
+                                    "messageType": "editUserName",
+                                    "payload": { "others": room_data.others_names },
+                                }))
+                                .await;
+                        }
                     }
                 }
             }
+
+            // The owner's pending list shows names too
+            send_pending_list(&state, &room_uid).await?;
         }
         "editIsAbsent" => {
+            if is_banned(&state, &room_uid, &user_uid).await? {
+                return Err("User is banned from this room".into());
+            }
+            ensure_open(&state, &room_uid, &user_uid).await?;
+
             let user_of_room: Result<UserOfRoom, sqlx::Error> = sqlx::query_as(
                 r#"
                     SELECT * FROM users_of_rooms
@@ -394,10 +335,12 @@ pub async fn handle_websocket_message(
                 Err(e) => return Err(e.into()),
             };
 
-            if user_of_room.is_some() && user_of_room.unwrap().is_owner {
+            if user_of_room.as_ref().is_some_and(|user| user.is_owner) {
                 return Err("Owner can't be absent.".into());
             }
 
+            let is_pending = user_of_room.is_none() && requires_approval(&state, &room_uid).await?;
+
             // TODO: OR get default name!
             let user_name: String = serde_json::from_value(msg.payload["user_name"].clone())?;
             let absent_reason: Option<String> =
@@ -410,8 +353,8 @@ pub async fn handle_websocket_message(
             // Set absent, and if user isn't in room add them
             let _ = sqlx::query!(
                 r#"
-                    INSERT IGNORE INTO users_of_rooms (user_uid, room_uid, name, is_owner, is_absent, absent_reason)
-                    VALUES (?, ?, ?, ?, ?, ?) ON DUPLICATE KEY UPDATE is_absent=?, absent_reason=?;
+                    INSERT IGNORE INTO users_of_rooms (user_uid, room_uid, name, is_owner, is_absent, absent_reason, is_pending)
+                    VALUES (?, ?, ?, ?, ?, ?, ?) ON DUPLICATE KEY UPDATE is_absent=?, absent_reason=?;
                 "#,
                 user_uid,
                 room_uid,
@@ -419,6 +362,7 @@ pub async fn handle_websocket_message(
                 false,
                 is_absent,
                 absent_reason,
+                is_pending,
 
                 is_absent,
                 absent_reason
@@ -428,7 +372,10 @@ pub async fn handle_websocket_message(
 
             if let Some(room) = state.rooms.lock().await.get(&room_uid) {
                 for (this_user_uid, user_wsc) in room.iter() {
-                    if let Ok(room_data) = process_room_data(&state, &room_uid, &this_user_uid).await {
+                    let viewer_tz = viewer_timezone(&state, &room_uid, this_user_uid).await;
+                    if let Ok(room_data) =
+                        process_room_data(&state, &room_uid, this_user_uid, viewer_tz).await
+                    {
                         let msg_type = if *this_user_uid == user_uid {
                             "userSetAbsentReason"
                         } else {
@@ -440,6 +387,7 @@ pub async fn handle_websocket_message(
                                 "messageType": msg_type,
                                 "payload": {
                                     "othersSchedule": room_data.others_schedule,
+                                    "othersIfNeeded": room_data.others_if_needed,
                                     "others": room_data.others_names,
                                     "absentReasons": room_data.absent_reasons
                                 }
@@ -448,6 +396,10 @@ pub async fn handle_websocket_message(
                     }
                 }
             }
+
+            if is_pending {
+                send_pending_list(&state, &room_uid).await?;
+            }
         }
         "removeParticipant" => {
             // Two modes: owner removes another (others_index), or non-owner removes self (leave: true)
@@ -455,6 +407,7 @@ pub async fn handle_websocket_message(
             struct RemovePayload {
                 others_index: Option<usize>,
                 leave: Option<bool>,
+                ban: Option<bool>,
             }
             let payload: RemovePayload = serde_json::from_value(msg.payload)?;
 
@@ -467,6 +420,7 @@ pub async fn handle_websocket_message(
             .await?;
 
             let is_self_leave = payload.leave.unwrap_or(false);
+            let ban = !is_self_leave && payload.ban.unwrap_or(false);
 
             if !is_self_leave && !user_of_room.is_owner {
                 return Err("Only the owner can remove other participants".into());
@@ -475,121 +429,916 @@ pub async fn handle_websocket_message(
                 return Err("Owner cannot leave their own room".into());
             }
 
-            let mut transaction = (&state.db_pool).begin().await?;
-
-            let room: Room = sqlx::query_as(
-                r#"
-                    SELECT uid, event_name, schedule_type,
-                           CAST(dates AS CHAR) as dates,
-                           CAST(days_of_week AS CHAR) as days_of_week,
-                           time_min, time_max, slot_length,
-                           CAST(schedule AS CHAR) as schedule,
-                           CAST(participants AS CHAR) as participants,
-                           timezone,
-                           expires_at
-                    FROM rooms
-                    WHERE uid=?
-                    FOR UPDATE
-                "#,
-            )
-            .bind(&room_uid)
-            .fetch_one(&mut *transaction)
-            .await?;
-
-            let mut participants: Vec<String> = serde_json::from_str(&room.participants)?;
-            let mut schedule: Vec<Vec<Vec<usize>>> = serde_json::from_str(&room.schedule)?;
-
-            // Determine target participant index
-            let target_p_index = if is_self_leave {
-                participants.iter().position(|p| p == &user_uid)
-                    .ok_or("User not found in participants")?
+            // Determine target user
+            let target_uid = if is_self_leave {
+                user_uid.clone()
             } else {
-                let sender_p_index = participants.iter().position(|p| p == &user_uid)
-                    .ok_or("Owner not found in participants")?;
                 let others_index = payload.others_index
                     .ok_or("others_index required for owner removal")?;
-                let mut others_idx = 0;
-                let mut found: Option<usize> = None;
-                for (p_idx, _) in participants.iter().enumerate() {
-                    if p_idx != sender_p_index {
-                        if others_idx == others_index {
-                            found = Some(p_idx);
-                            break;
-                        }
-                        others_idx += 1;
-                    }
-                }
-                found.ok_or("Invalid participant index")?
+                let (others, _) = split_others(load_members(&state, &room_uid).await?, &user_uid);
+                others.into_iter().nth(others_index)
+                    .ok_or("Invalid participant index")?
+                    .user_uid
             };
 
-            let target_uid = participants.get(target_p_index)
-                .ok_or("Participant index out of bounds")?.clone();
+            remove_from_room(&state, &room_uid, &target_uid, ban).await?;
 
-            // Remove target's index from all schedule cells and reindex
-            for row in schedule.iter_mut() {
-                for slot in row.iter_mut() {
-                    slot.retain(|&idx| idx != target_p_index);
-                    for idx in slot.iter_mut() {
-                        if *idx > target_p_index {
-                            *idx -= 1;
+            // Notify the removed user (if removed by owner), a banned user is
+            // also dropped from the room and disconnected
+            if !is_self_leave {
+                if let Some(room) = state.rooms.lock().await.get_mut(&room_uid) {
+                    if let Some(target_wsc) = room.get(&target_uid) {
+                        let _ = target_wsc
+                            .send_json(&json!({ "messageType": "removedFromRoom" }))
+                            .await;
+                    }
+                    if ban {
+                        if let Some(target_wsc) = room.remove(&target_uid) {
+                            let _ = target_wsc
+                                .send(tide_websockets::Message::Close(None))
+                                .await;
                         }
                     }
                 }
             }
 
-            participants.remove(target_p_index);
+            // Broadcast updated state to remaining users
+            broadcast_schedule(&state, &room_uid, Some(&target_uid)).await;
+        }
+        "setRequireApproval" => {
+            #[derive(Deserialize)]
+            struct RequireApprovalPayload {
+                require_approval: bool,
+            }
+            let payload: RequireApprovalPayload = serde_json::from_value(msg.payload)?;
+
+            if !is_room_owner(&state, &room_uid, &user_uid).await? {
+                return Err("Only the owner can change approval settings".into());
+            }
+
+            sqlx::query("UPDATE rooms SET require_approval=? WHERE uid=?")
+                .bind(payload.require_approval)
+                .bind(&room_uid)
+                .execute(&state.db_pool)
+                .await?;
+
+            // Nobody should be stuck waiting on a queue that no longer exists
+            if !payload.require_approval {
+                sqlx::query("UPDATE users_of_rooms SET is_pending=FALSE WHERE room_uid=?")
+                    .bind(&room_uid)
+                    .execute(&state.db_pool)
+                    .await?;
+            }
+
+            if let Some(room) = state.rooms.lock().await.get(&room_uid) {
+                for user_wsc in room.values() {
+                    let _ = user_wsc
+                        .send_json(&json!({
+                            "messageType": "setRequireApproval",
+                            "payload": { "requireApproval": payload.require_approval },
+                        }))
+                        .await;
+                }
+            }
+
+            if !payload.require_approval {
+                broadcast_schedule(&state, &room_uid, None).await;
+                send_pending_list(&state, &room_uid).await?;
+            }
+        }
+        "setRequired" => {
+            // Without others_index the owner flags themselves
+            #[derive(Deserialize)]
+            struct SetRequiredPayload {
+                others_index: Option<usize>,
+                required: bool,
+            }
+            let payload: SetRequiredPayload = serde_json::from_value(msg.payload)?;
 
-            sqlx::query!(
-                "UPDATE rooms SET schedule=?, participants=? WHERE uid=?",
-                json!(schedule),
-                json!(participants),
-                room_uid,
-            )
-            .execute(&mut *transaction)
-            .await?;
+            if !is_room_owner(&state, &room_uid, &user_uid).await? {
+                return Err("Only the owner can mark participants as required".into());
+            }
 
-            sqlx::query("DELETE FROM users_of_rooms WHERE user_uid=? AND room_uid=?")
+            let target_uid = match payload.others_index {
+                Some(others_index) => {
+                    let (others, _) = split_others(load_members(&state, &room_uid).await?, &user_uid);
+                    others
+                        .into_iter()
+                        .nth(others_index)
+                        .ok_or("Invalid participant index")?
+                        .user_uid
+                }
+                None => user_uid.clone(),
+            };
+
+            sqlx::query("UPDATE users_of_rooms SET is_required=? WHERE user_uid=? AND room_uid=?")
+                .bind(payload.required)
                 .bind(&target_uid)
                 .bind(&room_uid)
-                .execute(&mut *transaction)
+                .execute(&state.db_pool)
                 .await?;
 
-            transaction.commit().await?;
+            broadcast_schedule(&state, &room_uid, None).await;
+        }
+        "finalizeEvent" => {
+            #[derive(Deserialize)]
+            struct FinalizePayload {
+                day_index: usize,
+                start_slot: usize,
+                end_slot: usize,
+                lock_edits: Option<bool>,
+            }
+            let payload: FinalizePayload = serde_json::from_value(msg.payload)?;
+
+            if !is_room_owner(&state, &room_uid, &user_uid).await? {
+                return Err("Only the owner can finalize the event".into());
+            }
+
+            let room: Room = fetch_room(&state.db_pool, &room_uid, false).await?;
+            let schedule: Vec<Vec<Vec<usize>>> = serde_json::from_str(&room.schedule)?;
+            let slot_count = schedule
+                .get(payload.day_index)
+                .ok_or("Invalid day index")?
+                .len();
+            if payload.start_slot >= payload.end_slot || payload.end_slot > slot_count {
+                return Err("Invalid slot range".into());
+            }
+
+            let finalized = Finalized {
+                day_index: payload.day_index,
+                start_slot: payload.start_slot,
+                end_slot: payload.end_slot,
+                lock_edits: payload.lock_edits.unwrap_or(false),
+            };
+
+            sqlx::query("UPDATE rooms SET finalized=? WHERE uid=?")
+                .bind(json!(finalized))
+                .bind(&room_uid)
+                .execute(&state.db_pool)
+                .await?;
+
+            broadcast_finalized(&state, &room_uid, Some(&finalized)).await;
+        }
+        "unfinalizeEvent" => {
+            if !is_room_owner(&state, &room_uid, &user_uid).await? {
+                return Err("Only the owner can unfinalize the event".into());
+            }
+
+            sqlx::query("UPDATE rooms SET finalized=NULL WHERE uid=?")
+                .bind(&room_uid)
+                .execute(&state.db_pool)
+                .await?;
+
+            broadcast_finalized(&state, &room_uid, None).await;
+        }
+        "editRoomSettings" => {
+            let payload: RoomSettingsPayload = serde_json::from_value(msg.payload)?;
+
+            if !is_room_owner(&state, &room_uid, &user_uid).await? {
+                return Err("Only the owner can edit the room settings".into());
+            }
+
+            edit_room_settings(&state, &room_uid, payload).await?;
+            broadcast_room_settings(&state, &room_uid).await;
+        }
+        "copyWeek" => {
+            // Without to_weeks it goes to every other week
+            #[derive(Deserialize)]
+            struct CopyWeekPayload {
+                from_week: usize,
+                to_weeks: Option<Vec<usize>>,
+            }
+            let payload: CopyWeekPayload = serde_json::from_value(msg.payload)?;
+
+            let room: Room = fetch_room(&state.db_pool, &room_uid, false).await?;
+            if room.schedule_type != 2 {
+                return Err("Only multi-week rooms have weeks to copy".into());
+            }
+
+            let participants: Vec<String> = serde_json::from_str(&room.participants)?;
+            let p_idx = participants
+                .iter()
+                .position(|p| *p == user_uid)
+                .ok_or("No availability to copy")?;
+            let schedule: schedule::Grid = serde_json::from_str(&room.schedule)?;
+            let if_needed = schedule::conform(serde_json::from_str(&room.if_needed)?, &schedule);
+            let mut preferences = schedule::preferences_of(&schedule, &if_needed, Some(p_idx));
+
+            schedule::copy_week(
+                &mut preferences,
+                &schedule::column_dates(&room, Utc::now().date_naive())?,
+                &schedule::column_windows(&room)?,
+                payload.from_week,
+                payload.to_weeks.as_deref(),
+            );
+
+            let preferences: Vec<Vec<Preference>> = preferences
+                .into_iter()
+                .map(|day| day.into_iter().map(Preference::Level).collect())
+                .collect();
+            save_user_schedule(&state, &room_uid, &user_uid, String::new(), &preferences).await?;
+            send_user_schedule(&state, &room_uid, &user_uid).await;
+        }
+        "setDeadline" => {
+            #[derive(Deserialize)]
+            struct DeadlinePayload {
+                deadline: String,
+            }
+            let payload: DeadlinePayload = serde_json::from_value(msg.payload)?;
+            let deadline = time_new::OffsetDateTime::parse(&payload.deadline, &Rfc3339)?;
+
+            if !is_room_owner(&state, &room_uid, &user_uid).await? {
+                return Err("Only the owner can set a deadline".into());
+            }
+
+            // One already past is announced right here rather than by the background task
+            let passed = deadline <= time_new::OffsetDateTime::now_utc();
+            sqlx::query("UPDATE rooms SET response_deadline=?, deadline_announced=? WHERE uid=?")
+                .bind(deadline)
+                .bind(passed)
+                .bind(&room_uid)
+                .execute(&state.db_pool)
+                .await?;
+
+            broadcast_deadline(&state, &room_uid, Some(deadline)).await;
+        }
+        "reopenRoom" => {
+            if !is_room_owner(&state, &room_uid, &user_uid).await? {
+                return Err("Only the owner can reopen the room".into());
+            }
+
+            sqlx::query(
+                "UPDATE rooms SET response_deadline=NULL, deadline_announced=FALSE WHERE uid=?",
+            )
+            .bind(&room_uid)
+            .execute(&state.db_pool)
+            .await?;
+
+            broadcast_deadline(&state, &room_uid, None).await;
+        }
+        "approveParticipant" | "rejectParticipant" => {
+            #[derive(Deserialize)]
+            struct PendingPayload {
+                pending_index: usize,
+            }
+            let payload: PendingPayload = serde_json::from_value(msg.payload.clone())?;
+
+            if !is_room_owner(&state, &room_uid, &user_uid).await? {
+                return Err("Only the owner can approve or reject participants".into());
+            }
+
+            let (_, pending) = split_others(load_members(&state, &room_uid).await?, &user_uid);
+            let target_uid = pending
+                .into_iter()
+                .nth(payload.pending_index)
+                .ok_or("Invalid pending index")?
+                .user_uid;
+
+            if msg.message_type == "approveParticipant" {
+                sqlx::query("UPDATE users_of_rooms SET is_pending=FALSE WHERE user_uid=? AND room_uid=?")
+                    .bind(&target_uid)
+                    .bind(&room_uid)
+                    .execute(&state.db_pool)
+                    .await?;
 
-            // Notify the removed user (if removed by owner)
-            if !is_self_leave {
                 if let Some(room) = state.rooms.lock().await.get(&room_uid) {
                     if let Some(target_wsc) = room.get(&target_uid) {
                         let _ = target_wsc
-                            .send_json(&json!({ "messageType": "removedFromRoom" }))
+                            .send_json(&json!({ "messageType": "participantApproved" }))
                             .await;
                     }
                 }
-            }
 
-            // Broadcast updated state to remaining users
-            if let Some(room) = state.rooms.lock().await.get(&room_uid) {
-                for (wsc_user_uid, wsc) in room.iter() {
-                    if *wsc_user_uid != target_uid {
-                        if let Ok(room_data) = process_room_data(&state, &room_uid, wsc_user_uid).await {
-                            let _ = wsc
-                                .send_json(&json!({
-                                    "messageType": "editSchedule",
-                                    "payload": {
-                                        "userName": room_data.user_name,
-                                        "others": room_data.others_names,
-                                        "othersSchedule": room_data.others_schedule,
-                                        "absentReasons": room_data.absent_reasons
-                                    }
-                                }))
-                                .await;
-                        }
+                broadcast_schedule(&state, &room_uid, None).await;
+            } else {
+                remove_from_room(&state, &room_uid, &target_uid, false).await?;
+
+                if let Some(room) = state.rooms.lock().await.get(&room_uid) {
+                    if let Some(target_wsc) = room.get(&target_uid) {
+                        let _ = target_wsc
+                            .send_json(&json!({ "messageType": "removedFromRoom" }))
+                            .await;
                     }
                 }
             }
+
+            send_pending_list(&state, &room_uid).await?;
+        }
+        "listBans" => {
+            if !is_room_owner(&state, &room_uid, &user_uid).await? {
+                return Err("Only the owner can list bans".into());
+            }
+
+            send_ban_list(&state, &room_uid, &user_uid).await?;
+        }
+        "liftBan" => {
+            #[derive(Deserialize)]
+            struct LiftBanPayload {
+                ban_id: u32,
+            }
+            let payload: LiftBanPayload = serde_json::from_value(msg.payload)?;
+
+            if !is_room_owner(&state, &room_uid, &user_uid).await? {
+                return Err("Only the owner can lift bans".into());
+            }
+
+            sqlx::query("DELETE FROM room_bans WHERE id=? AND room_uid=?")
+                .bind(payload.ban_id)
+                .bind(&room_uid)
+                .execute(&state.db_pool)
+                .await?;
+
+            send_ban_list(&state, &room_uid, &user_uid).await?;
         }
         _ => return Err("Unknown message type".into()),
     };
 
     Ok(())
 }
+
+/// Maps a grid in `viewer_tz`'s shape back onto the room's. Slots the viewer
+/// can't see keep the user's current level.
+async fn to_room_time(
+    state: &State,
+    room_uid: &str,
+    user_uid: &str,
+    viewer_tz: Tz,
+    user_schedule: &[Vec<Preference>],
+) -> Result<Vec<Vec<Preference>>, Box<dyn std::error::Error>> {
+    let room: Room = fetch_room(&state.db_pool, room_uid, false).await?;
+    // Poll options are sent as they are, see `process_room_data`
+    if viewer_tz.name() == room.timezone || room.schedule_type == 3 {
+        return Ok(user_schedule.to_vec());
+    }
+
+    let participants: Vec<String> = serde_json::from_str(&room.participants)?;
+    let schedule: Vec<Vec<Vec<usize>>> = serde_json::from_str(&room.schedule)?;
+    let if_needed = schedule::conform(serde_json::from_str(&room.if_needed)?, &schedule);
+    let user_index = participants.iter().position(|p| p == user_uid);
+
+    let today = Utc::now().with_timezone(&viewer_tz).date_naive();
+    let layout = ViewerLayout::new(&room, &schedule, viewer_tz, today)?;
+
+    let current: Vec<Vec<Preference>> = schedule::preferences_of(&schedule, &if_needed, user_index)
+        .into_iter()
+        .map(|day| day.into_iter().map(Preference::Level).collect())
+        .collect();
+
+    Ok(layout.to_room(user_schedule, current))
+}
+
+/// Sends the room's ban list to the owner's connection
+async fn send_ban_list(
+    state: &State,
+    room_uid: &str,
+    owner_uid: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let bans: Vec<(u32, String, time_new::OffsetDateTime)> = sqlx::query_as(
+        "SELECT id, name, banned_at FROM room_bans WHERE room_uid=? ORDER BY banned_at",
+    )
+    .bind(room_uid)
+    .fetch_all(&state.db_pool)
+    .await?;
+
+    let bans: Vec<_> = bans
+        .into_iter()
+        .map(|(id, name, banned_at)| {
+            json!({
+                "id": id,
+                "name": name,
+                "bannedAt": format_timestamp(banned_at),
+            })
+        })
+        .collect();
+
+    if let Some(room) = state.rooms.lock().await.get(room_uid) {
+        if let Some(owner_wsc) = room.get(owner_uid) {
+            let _ = owner_wsc
+                .send_json(&json!({
+                    "messageType": "bans",
+                    "payload": { "bans": bans },
+                }))
+                .await;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the user's availability and membership from the room, optionally
+/// banning them so they can't join again
+async fn remove_from_room(
+    state: &State,
+    room_uid: &str,
+    target_uid: &str,
+    ban: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut transaction = state.db_pool.begin().await?;
+
+    let room: Room = fetch_room(&mut *transaction, room_uid, true).await?;
+
+    let mut participants: Vec<String> = serde_json::from_str(&room.participants)?;
+    let mut schedule: Vec<Vec<Vec<usize>>> = serde_json::from_str(&room.schedule)?;
+    let mut if_needed: Vec<Vec<Vec<usize>>> = serde_json::from_str(&room.if_needed)?;
+
+    // Absent-only users have nothing in the schedule
+    if let Some(target_p_index) = participants.iter().position(|p| p == target_uid) {
+        // Remove target's index from all schedule cells and reindex
+        schedule::remove_participant(&mut schedule, target_p_index);
+        schedule::remove_participant(&mut if_needed, target_p_index);
+
+        participants.remove(target_p_index);
+
+        sqlx::query("UPDATE rooms SET schedule=?, if_needed=?, participants=? WHERE uid=?")
+            .bind(json!(schedule))
+            .bind(json!(if_needed))
+            .bind(json!(participants))
+            .bind(room_uid)
+            .execute(&mut *transaction)
+            .await?;
+    }
+
+    // Keep the name so the owner can recognise them in the ban list
+    if ban {
+        sqlx::query(
+            r#"
+            INSERT IGNORE INTO room_bans (room_uid, user_uid, name)
+            SELECT room_uid, user_uid, name FROM users_of_rooms
+            WHERE user_uid=? AND room_uid=?
+            "#,
+        )
+        .bind(target_uid)
+        .bind(room_uid)
+        .execute(&mut *transaction)
+        .await?;
+    }
+
+    sqlx::query("DELETE FROM users_of_rooms WHERE user_uid=? AND room_uid=?")
+        .bind(target_uid)
+        .bind(room_uid)
+        .execute(&mut *transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Changes to the room's columns, hours or slot length, anything left out
+/// stays as it is. Polls only change their options.
+/// A new `time_range` without `day_windows` puts every column on it.
+#[derive(Deserialize)]
+struct RoomSettingsPayload {
+    dates: Option<ScheduleDates>,
+    time_range: Option<TimeRange>,
+    day_windows: Option<Vec<TimeRange>>,
+    slot_length: Option<u8>,
+    /// Only matters when slots get longer
+    #[serde(default)]
+    merge: MergeRule,
+}
+
+/// Reshapes the room and carries everyone's availability, and the finalized
+/// time if it's still there, over to the slots at the same date and time,
+/// resampled if the slot length changes
+async fn edit_room_settings(
+    state: &State,
+    room_uid: &str,
+    payload: RoomSettingsPayload,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut transaction = state.db_pool.begin().await?;
+
+    let room: Room = fetch_room(&mut *transaction, room_uid, true).await?;
+    let edited = edited_room(&room, payload)?;
+
+    sqlx::query(
+        r#"
+        UPDATE rooms
+        SET dates=?, days_of_week=?, options=?, start_minute=?, end_minute=?, day_windows=?,
+            slot_length=?, schedule=?, if_needed=?, finalized=?
+        WHERE uid=?
+        "#,
+    )
+    .bind(&edited.dates)
+    .bind(&edited.days_of_week)
+    .bind(&edited.options)
+    .bind(edited.start_minute)
+    .bind(edited.end_minute)
+    .bind(&edited.day_windows)
+    .bind(edited.slot_length)
+    .bind(&edited.schedule)
+    .bind(&edited.if_needed)
+    .bind(&edited.finalized)
+    .bind(room_uid)
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// `room` with the payload's settings and its grids and finalized time
+/// carried over to them
+fn edited_room(
+    room: &Room,
+    payload: RoomSettingsPayload,
+) -> Result<Room, Box<dyn std::error::Error>> {
+    let mut edited = room.clone();
+    edited.slot_length = payload.slot_length.unwrap_or(room.slot_length);
+
+    match payload.dates {
+        Some(ScheduleDates::Dates(dates))
+            if matches!(room.schedule_type, 0 | 2) && !dates.is_empty() =>
+        {
+            // A column without a readable date would lose everyone's answers
+            let mut parsed = dates
+                .iter()
+                .map(|date| schedule::parse_room_date(date))
+                .collect::<Option<Vec<_>>>()
+                .ok_or("Invalid date")?;
+            parsed.sort();
+            parsed.dedup();
+            if parsed.len() != dates.len() {
+                return Err("Dates must be distinct".into());
+            }
+            edited.dates = json!(dates).to_string();
+        }
+        Some(ScheduleDates::Weeks {
+            start_date,
+            mut days_of_week,
+            week_count,
+        }) if room.schedule_type == 2 => {
+            let dates = schedule::week_dates(&start_date, &days_of_week, week_count)
+                .ok_or("Invalid weeks")?;
+            days_of_week.sort();
+            days_of_week.dedup();
+            edited.dates = json!(dates).to_string();
+            edited.days_of_week = json!(days_of_week).to_string();
+        }
+        Some(ScheduleDates::DaysOfWeek(days)) if room.schedule_type == 1 && !days.is_empty() => {
+            if days.iter().any(|&day| day > 6) {
+                return Err("Invalid day of the week".into());
+            }
+            edited.days_of_week = json!(days).to_string();
+        }
+        Some(ScheduleDates::Options { options }) if room.schedule_type == 3 => {
+            let options = schedule::poll_options(options).ok_or("Invalid options")?;
+            edited.options = Some(json!(options).to_string());
+        }
+        Some(_) => return Err("Dates must be non-empty and of the room's kind".into()),
+        None => {}
+    }
+
+    // A poll's one slot per option has no hours to change
+    if room.schedule_type == 3
+        && (payload.time_range.is_some()
+            || payload.day_windows.is_some()
+            || payload.slot_length.is_some())
+    {
+        return Err("Polls have no hours".into());
+    }
+
+    if let Some(time_range) = &payload.time_range {
+        checked_window(time_range, edited.slot_length).ok_or("Invalid time range")?;
+        edited.start_minute = time_range.start_minute();
+        edited.end_minute = time_range.end_minute();
+    }
+
+    let column_keys = |room: &Room| -> Result<Vec<serde_json::Value>, serde_json::Error> {
+        match room.schedule_type {
+            1 => serde_json::from_str(&room.days_of_week),
+            3 => serde_json::from_str(room.options.as_deref().unwrap_or("[]")),
+            _ => serde_json::from_str(&room.dates),
+        }
+    };
+    let new_keys = column_keys(&edited)?;
+
+    let day_windows: Option<Vec<DayWindow>> = match (&payload.day_windows, &payload.time_range) {
+        (Some(day_windows), _) => {
+            if day_windows.len() != new_keys.len()
+                || day_windows
+                    .iter()
+                    .any(|range| checked_window(range, edited.slot_length).is_none())
+            {
+                return Err("Invalid day windows".into());
+            }
+            Some(day_windows.iter().map(TimeRange::day_window).collect())
+        }
+        (None, Some(_)) => None,
+        // Columns that stay keep their own hours, new ones get the room's
+        (None, None) => match &room.day_windows {
+            Some(day_windows) => {
+                let old_windows: Option<Vec<DayWindow>> = serde_json::from_str(day_windows)?;
+                let old_keys = column_keys(room)?;
+                old_windows.map(|old_windows| {
+                    new_keys
+                        .iter()
+                        .map(|key| {
+                            old_keys
+                                .iter()
+                                .position(|old_key| old_key == key)
+                                .and_then(|old_index| old_windows.get(old_index).copied())
+                                .unwrap_or(DayWindow {
+                                    start_minute: edited.start_minute,
+                                    end_minute: edited.end_minute,
+                                })
+                        })
+                        .collect()
+                })
+            }
+            None => None,
+        },
+    };
+    edited.day_windows = day_windows.map(|day_windows| json!(day_windows).to_string());
+
+    // Hours carried over from before have to fit a new slot length too
+    let windows = schedule::column_windows(&edited)?;
+    if windows.iter().any(|window| {
+        *window != schedule::TimeWindow::OPTION
+            && (window.slot_count() == 0 || !window.length().is_multiple_of(window.slot_length))
+    }) {
+        return Err("Slot length doesn't divide the room's hours".into());
+    }
+
+    let old_schedule: schedule::Grid = serde_json::from_str(&room.schedule)?;
+    let old_if_needed = schedule::conform(serde_json::from_str(&room.if_needed)?, &old_schedule);
+    let (new_schedule, new_if_needed, finalized) = if room.schedule_type == 3 {
+        // A poll's answers stay with their option's text
+        let columns = schedule::option_columns(
+            &schedule::poll_options_of(room)?,
+            &schedule::poll_options_of(&edited)?,
+        );
+        let finalized = parse_finalized(room)?.and_then(|finalized| {
+            Some(Finalized {
+                day_index: columns
+                    .iter()
+                    .position(|&column| column == Some(finalized.day_index))?,
+                ..finalized
+            })
+        });
+        (
+            schedule::select_columns(&old_schedule, &columns),
+            schedule::select_columns(&old_if_needed, &columns),
+            finalized,
+        )
+    } else {
+        let (from_times, to_times) =
+            (schedule::slot_times(room)?, schedule::slot_times(&edited)?);
+        let from = SlotLayout {
+            times: &from_times,
+            slot_length: u32::from(room.slot_length),
+        };
+        let to = SlotLayout {
+            times: &to_times,
+            slot_length: u32::from(edited.slot_length),
+        };
+        let (new_schedule, new_if_needed) =
+            schedule::resample(&old_schedule, &old_if_needed, &from, &to, payload.merge);
+        let finalized = parse_finalized(room)?
+            .and_then(|finalized| schedule::remap_finalized(&finalized, &from, &to));
+        (new_schedule, new_if_needed, finalized)
+    };
+
+    edited.schedule = json!(new_schedule).to_string();
+    edited.if_needed = json!(new_if_needed).to_string();
+    edited.finalized = finalized.map(|finalized| json!(finalized).to_string());
+    Ok(edited)
+}
+
+/// Sends everyone connected the room's new columns, hours and slots with the
+/// availability carried over to them, each in the shape they see it
+async fn broadcast_room_settings(state: &State, room_uid: &str) {
+    if let Some(room) = state.rooms.lock().await.get(room_uid) {
+        for (wsc_user_uid, wsc) in room.iter() {
+            let viewer_tz = viewer_timezone(state, room_uid, wsc_user_uid).await;
+            if let Ok(room_data) = process_room_data(state, room_uid, wsc_user_uid, viewer_tz).await {
+                let _ = wsc
+                    .send_json(&json!({
+                        "messageType": "roomSettings",
+                        "payload": {
+                            "dates": room_data.dates,
+                            "daysOfWeek": room_data.days_of_week,
+                            "timeRange": room_data.time_range,
+                            "dayWindows": room_data.day_windows,
+                            "columnWeeks": room_data.column_weeks,
+                            "options": room_data.options,
+                            "slotLength": room_data.slot_length,
+                            "userSchedule": room_data.user_schedule,
+                            "userPreferences": room_data.user_preferences,
+                            "othersSchedule": room_data.others_schedule,
+                            "othersIfNeeded": room_data.others_if_needed,
+                            "finalized": room_data.finalized.as_ref().map(finalized_payload),
+                            "utcOffsets": room_data.utc_offsets,
+                            "dstSlots": room_data.dst_slots,
+                            "viewer": room_data.viewer,
+                        }
+                    }))
+                    .await;
+            }
+        }
+    }
+}
+
+/// Sends the user their own availability after the server changed it for them
+async fn send_user_schedule(state: &State, room_uid: &str, user_uid: &str) {
+    let viewer_tz = viewer_timezone(state, room_uid, user_uid).await;
+    let Ok(room_data) = process_room_data(state, room_uid, user_uid, viewer_tz).await else {
+        return;
+    };
+
+    if let Some(room) = state.rooms.lock().await.get(room_uid) {
+        if let Some(wsc) = room.get(user_uid) {
+            let _ = wsc
+                .send_json(&json!({
+                    "messageType": "userSchedule",
+                    "payload": {
+                        "userSchedule": room_data.user_schedule,
+                        "userPreferences": room_data.user_preferences,
+                    }
+                }))
+                .await;
+        }
+    }
+}
+
+/// Refuses changes from anyone but the owner once the deadline has passed
+async fn ensure_open(
+    state: &State,
+    room_uid: &str,
+    user_uid: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let room: Room = fetch_room(&state.db_pool, room_uid, false).await?;
+    if is_past_deadline(&room) && !is_room_owner(state, room_uid, user_uid).await? {
+        return Err("Responses are closed".into());
+    }
+    Ok(())
+}
+
+/// Tells everyone connected, read-only viewers too, about the deadline and
+/// whether it has passed
+async fn broadcast_deadline(
+    state: &State,
+    room_uid: &str,
+    deadline: Option<time_new::OffsetDateTime>,
+) {
+    let closed = deadline.is_some_and(|deadline| deadline <= time_new::OffsetDateTime::now_utc());
+
+    if let Some(room) = state.rooms.lock().await.get(room_uid) {
+        for wsc in room.values() {
+            let _ = wsc
+                .send_json(&json!({
+                    "messageType": "deadline",
+                    "payload": {
+                        "responseDeadline": deadline.map(format_timestamp),
+                        "responsesClosed": closed,
+                    },
+                }))
+                .await;
+        }
+    }
+}
+
+/// Broadcasts the deadlines that passed since the last run, called periodically
+pub async fn announce_deadlines(state: &State) -> Result<(), sqlx::Error> {
+    let passed: Vec<(String, time_new::OffsetDateTime)> = sqlx::query_as(
+        r#"
+        SELECT uid, response_deadline FROM rooms
+        WHERE response_deadline <= NOW() AND NOT deadline_announced
+        "#,
+    )
+    .fetch_all(&state.db_pool)
+    .await?;
+
+    for (room_uid, deadline) in passed {
+        sqlx::query("UPDATE rooms SET deadline_announced=TRUE WHERE uid=?")
+            .bind(&room_uid)
+            .execute(&state.db_pool)
+            .await?;
+
+        broadcast_deadline(state, &room_uid, Some(deadline)).await;
+    }
+
+    Ok(())
+}
+
+/// Tells everyone connected, read-only viewers too, about the decision
+async fn broadcast_finalized(state: &State, room_uid: &str, finalized: Option<&Finalized>) {
+    let finalized = finalized.map(finalized_payload);
+
+    if let Some(room) = state.rooms.lock().await.get(room_uid) {
+        for wsc in room.values() {
+            let _ = wsc
+                .send_json(&json!({
+                    "messageType": "finalized",
+                    "payload": { "finalized": finalized },
+                }))
+                .await;
+        }
+    }
+}
+
+fn finalized_payload(finalized: &Finalized) -> serde_json::Value {
+    json!({
+        "dayIndex": finalized.day_index,
+        "startSlot": finalized.start_slot,
+        "endSlot": finalized.end_slot,
+        "lockEdits": finalized.lock_edits,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn room() -> Room {
+        Room {
+            dates: json!(["Mon Mar 02 2026", "Wed Mar 04 2026"]).to_string(),
+            start_minute: 9 * 60,
+            end_minute: 12 * 60,
+            schedule: json!([[[0], [0, 1], []], [[1], [], [0]]]).to_string(),
+            if_needed: json!([[[], [1], []], [[], [], []]]).to_string(),
+            participants: json!(["a", "b"]).to_string(),
+            finalized: Some(
+                json!({ "day_index": 1, "start_slot": 0, "end_slot": 2, "lock_edits": false })
+                    .to_string(),
+            ),
+            ..Room::for_test()
+        }
+    }
+
+    fn edit(room: &Room, payload: serde_json::Value) -> Result<Room, Box<dyn std::error::Error>> {
+        edited_room(room, serde_json::from_value(payload).unwrap())
+    }
+
+    fn grid(json: &str) -> serde_json::Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn finalized_range(room: &Room) -> Option<(usize, usize, usize)> {
+        parse_finalized(room)
+            .unwrap()
+            .map(|finalized| (finalized.day_index, finalized.start_slot, finalized.end_slot))
+    }
+
+    #[test]
+    fn same_dates_and_hours_keep_everything() {
+        let room = room();
+        let edited = edit(
+            &room,
+            json!({
+                "dates": ["Mon Mar 02 2026", "Wed Mar 04 2026"],
+                "time_range": { "from_hour": 9, "to_hour": 12 },
+            }),
+        )
+        .unwrap();
+
+        assert_eq!(grid(&edited.schedule), grid(&room.schedule));
+        assert_eq!(grid(&edited.if_needed), grid(&room.if_needed));
+        assert_eq!(finalized_range(&edited), Some((1, 0, 2)));
+    }
+
+    #[test]
+    fn answers_follow_their_dates() {
+        // Monday goes, Friday comes, Wednesday moves to the front
+        let edited = edit(
+            &room(),
+            json!({ "dates": ["Wed Mar 04 2026", "Fri Mar 06 2026"] }),
+        )
+        .unwrap();
+
+        assert_eq!(grid(&edited.schedule), json!([[[1], [], [0]], [[], [], []]]));
+        assert_eq!(grid(&edited.if_needed), json!([[[], [], []], [[], [], []]]));
+        assert_eq!(finalized_range(&edited), Some((0, 0, 2)));
+    }
+
+    #[test]
+    fn removed_date_takes_the_finalized_time_along() {
+        let edited = edit(&room(), json!({ "dates": ["Mon Mar 02 2026"] })).unwrap();
+
+        assert_eq!(grid(&edited.schedule), json!([[[0], [0, 1], []]]));
+        assert_eq!(finalized_range(&edited), None);
+    }
+
+    #[test]
+    fn answers_follow_their_hours() {
+        let edited = edit(&room(), json!({ "time_range": { "from_hour": 10, "to_hour": 13 } }))
+            .unwrap();
+
+        assert_eq!(
+            grid(&edited.schedule),
+            json!([[[0, 1], [], []], [[], [0], []]])
+        );
+        assert_eq!(grid(&edited.if_needed), json!([[[1], [], []], [[], [], []]]));
+        // 9:00 to 11:00 isn't all there anymore
+        assert_eq!(finalized_range(&edited), None);
+    }
+
+    #[test]
+    fn unreadable_and_repeated_dates_are_refused() {
+        let room = room();
+        assert!(edit(&room, json!({ "dates": ["2026-03-02"] })).is_err());
+        assert!(edit(&room, json!({ "dates": ["Mon Mar 02 2026", "Mon Mar 02 2026"] })).is_err());
+        assert!(edit(&room, json!({ "dates": ["Mon Mar 02 2026"] })).is_ok());
+    }
+}
@@ -0,0 +1,1056 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::{OffsetComponents, OffsetName, Tz};
+
+const DATE_TIME_FORMAT: &str = "%Y%m%dT%H%M%S";
+const WEEKDAY_CODES: [&str; 7] = ["MO", "TU", "WE", "TH", "FR", "SA", "SU"];
+
+/// One VEVENT, in local time of `timezone`, or floating without one
+pub struct Event {
+    pub uid: String,
+    pub summary: String,
+    pub description: String,
+    pub url: String,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    pub timezone: Option<Tz>,
+    /// Repeats every week on the start's weekday
+    pub weekly: bool,
+}
+
+/// Renders a complete VCALENDAR (RFC 5545) holding `event`
+pub fn calendar(event: &Event, now: DateTime<Utc>) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//cmon.rsvp//rsvp//EN".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+        "METHOD:PUBLISH".to_string(),
+    ];
+
+    if let Some(tz) = event.timezone {
+        lines.extend(vtimezone(tz, event.start.year()));
+    }
+
+    let local_time = |time: NaiveDateTime| match event.timezone {
+        Some(tz) => format!(";TZID={}:{}", tz.name(), time.format(DATE_TIME_FORMAT)),
+        None => format!(":{}", time.format(DATE_TIME_FORMAT)),
+    };
+
+    lines.push("BEGIN:VEVENT".to_string());
+    lines.push(format!("UID:{}", escape_text(&event.uid)));
+    lines.push(format!("DTSTAMP:{}Z", now.format(DATE_TIME_FORMAT)));
+    lines.push(format!("DTSTART{}", local_time(event.start)));
+    lines.push(format!("DTEND{}", local_time(event.end)));
+    if event.weekly {
+        let weekday = event.start.weekday().num_days_from_monday() as usize;
+        lines.push(format!("RRULE:FREQ=WEEKLY;BYDAY={}", WEEKDAY_CODES[weekday]));
+    }
+    lines.push(format!("SUMMARY:{}", escape_text(&event.summary)));
+    lines.push(format!("DESCRIPTION:{}", escape_text(&event.description)));
+    lines.push(format!("URL:{}", event.url));
+    lines.push("END:VEVENT".to_string());
+    lines.push("END:VCALENDAR".to_string());
+
+    lines.iter().map(|line| fold_line(line)).collect()
+}
+
+/// Describes `tz` by the transitions it makes during `year`, each repeating
+/// yearly on the same weekday of the month.
+fn vtimezone(tz: Tz, year: i32) -> Vec<String> {
+    let mut lines = vec!["BEGIN:VTIMEZONE".to_string(), format!("TZID:{}", tz.name())];
+
+    let transitions = transitions_in_year(tz, year);
+    if transitions.is_empty() {
+        let offset = tz.offset_from_utc_datetime(&year_start(year));
+        lines.extend(observance(
+            "STANDARD",
+            NaiveDate::from_ymd_opt(1970, 1, 1)
+                .unwrap_or_default()
+                .and_hms_opt(0, 0, 0)
+                .unwrap_or_default(),
+            offset.fix().local_minus_utc(),
+            offset.fix().local_minus_utc(),
+            offset.abbreviation(),
+            None,
+        ));
+    }
+
+    for at in transitions {
+        let before = tz.offset_from_utc_datetime(&(at - Duration::minutes(1)));
+        let after = tz.offset_from_utc_datetime(&at);
+        let from = before.fix().local_minus_utc();
+
+        // DTSTART is the wall clock time just before the change
+        let local_start = at + Duration::seconds(i64::from(from));
+        let kind = if after.dst_offset().is_zero() { "STANDARD" } else { "DAYLIGHT" };
+
+        lines.extend(observance(
+            kind,
+            local_start,
+            from,
+            after.fix().local_minus_utc(),
+            after.abbreviation(),
+            Some(yearly_rule(local_start.date())),
+        ));
+    }
+
+    lines.push("END:VTIMEZONE".to_string());
+    lines
+}
+
+fn observance(
+    kind: &str,
+    start: NaiveDateTime,
+    offset_from: i32,
+    offset_to: i32,
+    name: Option<&str>,
+    rrule: Option<String>,
+) -> Vec<String> {
+    let mut lines = vec![
+        format!("BEGIN:{}", kind),
+        format!("DTSTART:{}", start.format(DATE_TIME_FORMAT)),
+        format!("TZOFFSETFROM:{}", format_offset(offset_from)),
+        format!("TZOFFSETTO:{}", format_offset(offset_to)),
+    ];
+    if let Some(name) = name {
+        lines.push(format!("TZNAME:{}", escape_text(name)));
+    }
+    if let Some(rrule) = rrule {
+        lines.push(format!("RRULE:{}", rrule));
+    }
+    lines.push(format!("END:{}", kind));
+    lines
+}
+
+fn year_start(year: i32) -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(year, 1, 1)
+        .unwrap_or_default()
+        .and_hms_opt(0, 0, 0)
+        .unwrap_or_default()
+}
+
+/// UTC instants, to the minute, at which `tz` changes its offset during `year`
+fn transitions_in_year(tz: Tz, year: i32) -> Vec<NaiveDateTime> {
+    let offset_at = |at: &NaiveDateTime| tz.offset_from_utc_datetime(at).fix().local_minus_utc();
+
+    let end = year_start(year + 1);
+    let mut transitions = Vec::new();
+    let mut at = year_start(year);
+    let mut offset = offset_at(&at);
+
+    while at < end {
+        let next = at + Duration::hours(1);
+        let next_offset = offset_at(&next);
+        if next_offset != offset {
+            // Narrow it down to the first minute with the new offset
+            let (mut low, mut high) = (at, next);
+            while high - low > Duration::minutes(1) {
+                let mid = low + (high - low) / 2;
+                if offset_at(&mid) == offset {
+                    low = mid;
+                } else {
+                    high = mid;
+                }
+            }
+            transitions.push(high);
+        }
+        at = next;
+        offset = next_offset;
+    }
+
+    transitions
+}
+
+/// `FREQ=YEARLY` on the same weekday of the month as `date`, counting from
+/// the end of the month when it falls in the last week
+fn yearly_rule(date: NaiveDate) -> String {
+    let days_in_month = days_in_month(date);
+
+    let week = if date.day() + 7 > days_in_month {
+        -1
+    } else {
+        ((date.day() - 1) / 7 + 1) as i32
+    };
+    let weekday = date.weekday().num_days_from_monday() as usize;
+
+    format!(
+        "FREQ=YEARLY;BYMONTH={};BYDAY={}{}",
+        date.month(),
+        week,
+        WEEKDAY_CODES[weekday]
+    )
+}
+
+/// `+HHMM`, or `+HHMMSS` for the odd historical offset
+fn format_offset(seconds: i32) -> String {
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let seconds = seconds.unsigned_abs();
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if seconds == 0 {
+        format!("{}{:02}{:02}", sign, hours, minutes)
+    } else {
+        format!("{}{:02}{:02}{:02}", sign, hours, minutes, seconds)
+    }
+}
+
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Splits lines longer than 75 octets, without breaking a character, and
+/// terminates them with CRLF
+fn fold_line(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 8);
+    let mut line_len = 0;
+    for c in line.chars() {
+        if line_len + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            line_len = 1;
+        }
+        folded.push(c);
+        line_len += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+/// A span the calendar's owner is busy in, or only tentatively
+pub struct Busy {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub tentative: bool,
+}
+
+/// Days an import may step through following recurrences, across all its events
+const MAX_RECURRENCE_STEPS: usize = 100_000;
+
+struct Property {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Joins folded lines back together
+fn unfold(ics: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in ics.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continued), Some(last)) => last.push_str(continued),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+/// `NAME;PARAM=value;PARAM="quoted":value`
+fn parse_property(line: &str) -> Option<Property> {
+    let mut in_quotes = false;
+    let colon = line.char_indices().find_map(|(i, c)| match c {
+        '"' => {
+            in_quotes = !in_quotes;
+            None
+        }
+        ':' if !in_quotes => Some(i),
+        _ => None,
+    })?;
+
+    let (head, value) = (&line[..colon], &line[colon + 1..]);
+    let mut parts = head.split(';');
+    let name = parts.next()?.trim().to_uppercase();
+    let params = parts
+        .filter_map(|param| param.split_once('='))
+        .map(|(key, value)| (key.trim().to_uppercase(), value.trim_matches('"').to_string()))
+        .collect();
+
+    Some(Property {
+        name,
+        params,
+        value: value.to_string(),
+    })
+}
+
+/// A DTSTART-like value as wall clock time in a zone
+#[derive(Clone, Copy)]
+struct Moment {
+    local: NaiveDateTime,
+    tz: Tz,
+    all_day: bool,
+}
+
+impl Moment {
+    fn parse(property: &Property, default_tz: Tz) -> Option<Self> {
+        Self::parse_value(property.value.trim(), property, default_tz)
+    }
+
+    fn parse_value(value: &str, property: &Property, default_tz: Tz) -> Option<Self> {
+        if property.param("VALUE") == Some("DATE") || value.len() == 8 {
+            let date = NaiveDate::parse_from_str(value, "%Y%m%d").ok()?;
+            return Some(Self {
+                local: date.and_hms_opt(0, 0, 0)?,
+                tz: default_tz,
+                all_day: true,
+            });
+        }
+
+        if let Some(utc) = value.strip_suffix('Z') {
+            return Some(Self {
+                local: NaiveDateTime::parse_from_str(utc, DATE_TIME_FORMAT).ok()?,
+                tz: Tz::UTC,
+                all_day: false,
+            });
+        }
+
+        // Zones we don't know, like Windows names, are read as the room's own
+        let tz = property
+            .param("TZID")
+            .and_then(|tzid| tzid.trim_start_matches('/').parse().ok())
+            .unwrap_or(default_tz);
+        Some(Self {
+            local: NaiveDateTime::parse_from_str(value, DATE_TIME_FORMAT).ok()?,
+            tz,
+            all_day: false,
+        })
+    }
+
+    /// Wall clock times skipped by a DST change are read an hour later
+    fn to_utc(self) -> DateTime<Utc> {
+        let local = self
+            .tz
+            .from_local_datetime(&self.local)
+            .earliest()
+            .or_else(|| {
+                self.tz
+                    .from_local_datetime(&(self.local + Duration::hours(1)))
+                    .earliest()
+            });
+        match local {
+            Some(local) => local.with_timezone(&Utc),
+            None => Utc.from_utc_datetime(&self.local),
+        }
+    }
+
+    /// When an instance starting here and lasting `length` is over. All-day
+    /// events keep whole days across DST changes, timed ones their exact length.
+    fn end(self, length: Duration) -> DateTime<Utc> {
+        if self.all_day {
+            match self.local.checked_add_signed(length) {
+                Some(local) => Self { local, ..self }.to_utc(),
+                None => DateTime::<Utc>::MAX_UTC,
+            }
+        } else {
+            self.to_utc()
+                .checked_add_signed(length)
+                .unwrap_or(DateTime::<Utc>::MAX_UTC)
+        }
+    }
+
+    fn on(self, date: NaiveDate) -> Self {
+        Self {
+            local: date.and_time(self.local.time()),
+            ..self
+        }
+    }
+}
+
+/// `[+-]P[nW][nD][T[nH][nM][nS]]`
+fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let (sign, value) = match value.strip_prefix('-') {
+        Some(value) => (-1, value),
+        None => (1, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let value = value.strip_prefix('P')?;
+
+    let mut duration = Duration::zero();
+    let mut number = String::new();
+    let mut in_time = false;
+    for c in value.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' => in_time = true,
+            _ => {
+                let n: i64 = number.parse().ok()?;
+                number.clear();
+                let part = match (c, in_time) {
+                    ('W', false) => Duration::try_weeks(n),
+                    ('D', false) => Duration::try_days(n),
+                    ('H', true) => Duration::try_hours(n),
+                    ('M', true) => Duration::try_minutes(n),
+                    ('S', true) => Duration::try_seconds(n),
+                    _ => None,
+                };
+                duration = duration.checked_add(&part?)?;
+            }
+        }
+    }
+
+    Some(duration * sign)
+}
+
+enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+struct Rule {
+    frequency: Frequency,
+    interval: i64,
+    count: Option<usize>,
+    until: Option<DateTime<Utc>>,
+    /// Weekdays from Monday, with an optional nth-in-month
+    by_day: Vec<(Option<i32>, u32)>,
+    by_month_day: Vec<i32>,
+    by_month: Vec<u32>,
+}
+
+impl Rule {
+    fn parse(value: &str, start: &Moment) -> Option<Self> {
+        let mut rule = Rule {
+            frequency: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_day: Vec::new(),
+            by_month_day: Vec::new(),
+            by_month: Vec::new(),
+        };
+        let mut frequency = None;
+
+        for part in value.trim().split(';') {
+            let Some((key, value)) = part.split_once('=') else {
+                continue;
+            };
+            match key.to_uppercase().as_str() {
+                "FREQ" => {
+                    frequency = match value.to_uppercase().as_str() {
+                        "DAILY" => Some(Frequency::Daily),
+                        "WEEKLY" => Some(Frequency::Weekly),
+                        "MONTHLY" => Some(Frequency::Monthly),
+                        "YEARLY" => Some(Frequency::Yearly),
+                        // Sub-daily rules are rare enough to not be worth it
+                        _ => return None,
+                    }
+                }
+                "INTERVAL" => rule.interval = value.parse().ok().filter(|&n| n > 0)?,
+                "COUNT" => rule.count = value.parse().ok(),
+                "UNTIL" => {
+                    let until = Property {
+                        name: "UNTIL".to_string(),
+                        params: Vec::new(),
+                        value: value.to_string(),
+                    };
+                    // A date-only UNTIL still includes that whole day
+                    rule.until = Moment::parse(&until, start.tz).map(|until| {
+                        if until.all_day {
+                            until.to_utc() + Duration::days(1)
+                        } else {
+                            until.to_utc()
+                        }
+                    });
+                }
+                "BYDAY" => {
+                    for day in value.split(',') {
+                        let day = day.trim().to_uppercase();
+                        let (ordinal, code) = day.split_at(day.len().saturating_sub(2));
+                        let weekday = WEEKDAY_CODES.iter().position(|c| *c == code)? as u32;
+                        let ordinal = if ordinal.is_empty() {
+                            None
+                        } else {
+                            Some(ordinal.trim_start_matches('+').parse().ok()?)
+                        };
+                        rule.by_day.push((ordinal, weekday));
+                    }
+                }
+                "BYMONTHDAY" => {
+                    for day in value.split(',') {
+                        rule.by_month_day.push(day.trim().parse().ok()?);
+                    }
+                }
+                "BYMONTH" => {
+                    for month in value.split(',') {
+                        rule.by_month.push(month.trim().parse().ok()?);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        rule.frequency = frequency?;
+        Some(rule)
+    }
+
+    fn matches(&self, date: NaiveDate, start: NaiveDate) -> bool {
+        let in_period = match self.frequency {
+            Frequency::Daily => (date - start).num_days() % self.interval == 0,
+            Frequency::Weekly => {
+                let start_week = start - Duration::days(i64::from(start.weekday().num_days_from_monday()));
+                ((date - start_week).num_days() / 7) % self.interval == 0
+            }
+            Frequency::Monthly => {
+                let months = (date.year() - start.year()) * 12 + date.month() as i32
+                    - start.month() as i32;
+                i64::from(months) % self.interval == 0
+            }
+            Frequency::Yearly => i64::from(date.year() - start.year()) % self.interval == 0,
+        };
+        if !in_period {
+            return false;
+        }
+
+        if !self.by_month.is_empty() && !self.by_month.contains(&date.month()) {
+            return false;
+        }
+
+        let days_in_month = days_in_month(date);
+        let weekday = date.weekday().num_days_from_monday();
+        let by_day = self.by_day.iter().any(|&(ordinal, day)| {
+            day == weekday
+                && match ordinal {
+                    // Nth weekday of the month, counted from its end when negative
+                    Some(n) if n > 0 => (date.day() as i32 - 1) / 7 + 1 == n,
+                    Some(n) if n < 0 => (days_in_month as i32 - date.day() as i32) / 7 + 1 == -n,
+                    _ => true,
+                }
+        });
+        let by_month_day = self.by_month_day.iter().any(|&day| {
+            if day > 0 {
+                date.day() as i32 == day
+            } else {
+                date.day() as i32 == days_in_month as i32 + day + 1
+            }
+        });
+
+        match (self.by_day.is_empty(), self.by_month_day.is_empty()) {
+            (false, false) => by_day && by_month_day,
+            (false, true) => by_day,
+            (true, false) => by_month_day,
+            (true, true) => match self.frequency {
+                Frequency::Daily => true,
+                Frequency::Weekly => date.weekday() == start.weekday(),
+                Frequency::Monthly => date.day() == start.day(),
+                Frequency::Yearly => {
+                    date.day() == start.day()
+                        && (!self.by_month.is_empty() || date.month() == start.month())
+                }
+            },
+        }
+    }
+
+    /// Instances of an event starting at `start` and lasting `length` that
+    /// overlap `from..to`, `start` itself counting as the first. Each day
+    /// looked at takes one of `steps`, `None` once they run out.
+    fn occurrences(
+        &self,
+        start: Moment,
+        length: Duration,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        steps: &mut usize,
+    ) -> Option<Vec<Moment>> {
+        let overlaps = |occurrence: &Moment| {
+            occurrence.to_utc() < to && occurrence.end(length) > from
+        };
+        let first = start.local.date();
+        let mut occurrences: Vec<Moment> = Some(start).filter(overlaps).into_iter().collect();
+        let mut counted = 1;
+
+        // COUNT has to be followed from the start, otherwise pick the rule up
+        // a day before anything reaching into the window could start, which
+        // leaves room for any zone's offset. `date` is the last day looked at.
+        let mut date = match self.count {
+            Some(_) => first,
+            None => from
+                .checked_sub_signed(length)
+                .and_then(|earliest| {
+                    earliest.date_naive().checked_sub_signed(Duration::days(2))
+                })
+                .map_or(first, |earliest| earliest.max(first)),
+        };
+
+        loop {
+            if self.count.is_some_and(|count| counted >= count) {
+                break;
+            }
+            let Some(next) = date.succ_opt() else {
+                break;
+            };
+            date = next;
+
+            *steps = steps.checked_sub(1)?;
+            let occurrence = start.on(date);
+            let at = occurrence.to_utc();
+            if at >= to || self.until.is_some_and(|rule_until| at > rule_until) {
+                break;
+            }
+            if self.matches(date, first) {
+                counted += 1;
+                if overlaps(&occurrence) {
+                    occurrences.push(occurrence);
+                }
+            }
+        }
+
+        Some(occurrences)
+    }
+}
+
+fn days_in_month(date: NaiveDate) -> u32 {
+    NaiveDate::from_ymd_opt(
+        if date.month() == 12 { date.year() + 1 } else { date.year() },
+        date.month() % 12 + 1,
+        1,
+    )
+    .and_then(|next_month| next_month.pred_opt())
+    .map(|last_day| last_day.day())
+    .unwrap_or(31)
+}
+
+#[derive(Default)]
+struct VEvent {
+    uid: String,
+    properties: Vec<Property>,
+}
+
+impl VEvent {
+    fn get(&self, name: &str) -> Option<&Property> {
+        self.properties.iter().find(|property| property.name == name)
+    }
+
+    fn value(&self, name: &str) -> Option<String> {
+        self.get(name).map(|property| property.value.trim().to_uppercase())
+    }
+}
+
+/// Busy spans of every event in `ics` overlapping `from..to`. Floating and
+/// all-day times are read in `default_tz`. Cancelled and transparent events
+/// don't count, tentative ones are marked.
+pub fn busy_intervals(
+    ics: &str,
+    default_tz: Tz,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<Busy>, &'static str> {
+    let mut events: Vec<VEvent> = Vec::new();
+    let mut current: Option<VEvent> = None;
+    // Properties of VALARMs and such nested in an event aren't the event's
+    let mut nested = 0;
+    let mut found_calendar = false;
+
+    for line in unfold(ics) {
+        let Some(property) = parse_property(&line) else {
+            continue;
+        };
+        match (property.name.as_str(), property.value.trim().to_uppercase().as_str()) {
+            ("BEGIN", "VCALENDAR") => found_calendar = true,
+            ("BEGIN", "VEVENT") => current = Some(VEvent::default()),
+            ("END", "VEVENT") => events.extend(current.take()),
+            ("BEGIN", _) if current.is_some() => nested += 1,
+            ("END", _) if current.is_some() && nested > 0 => nested -= 1,
+            _ => {
+                if let Some(event) = current.as_mut().filter(|_| nested == 0) {
+                    if property.name == "UID" {
+                        event.uid = property.value.clone();
+                    }
+                    event.properties.push(property);
+                }
+            }
+        }
+    }
+
+    if !found_calendar {
+        return Err("Not an iCalendar file");
+    }
+
+    // Instances moved or changed by a RECURRENCE-ID event are that event's business
+    let overridden: Vec<(String, DateTime<Utc>)> = events
+        .iter()
+        .filter_map(|event| {
+            let recurrence_id = Moment::parse(event.get("RECURRENCE-ID")?, default_tz)?;
+            Some((event.uid.clone(), recurrence_id.to_utc()))
+        })
+        .collect();
+
+    let mut busy = Vec::new();
+    let mut steps = MAX_RECURRENCE_STEPS;
+    for event in &events {
+        if event.value("STATUS").as_deref() == Some("CANCELLED")
+            || event.value("TRANSP").as_deref() == Some("TRANSPARENT")
+        {
+            continue;
+        }
+        let tentative = event.value("STATUS").as_deref() == Some("TENTATIVE");
+
+        let Some(start) = event
+            .get("DTSTART")
+            .and_then(|property| Moment::parse(property, default_tz))
+        else {
+            continue;
+        };
+        let end = event
+            .get("DTEND")
+            .and_then(|property| Moment::parse(property, default_tz));
+        let duration = event
+            .get("DURATION")
+            .and_then(|property| parse_duration(&property.value));
+
+        let length = match (end, duration) {
+            (Some(end), _) if start.all_day => end.local - start.local,
+            (Some(end), _) => end.to_utc() - start.to_utc(),
+            (None, Some(duration)) => duration,
+            (None, None) if start.all_day => Duration::days(1),
+            (None, None) => Duration::zero(),
+        };
+        if length <= Duration::zero() {
+            continue;
+        }
+
+        let is_override = event.get("RECURRENCE-ID").is_some();
+        let occurrences = match event.get("RRULE") {
+            Some(rrule) if !is_override => match Rule::parse(&rrule.value, &start) {
+                Some(rule) => rule
+                    .occurrences(start, length, from, to, &mut steps)
+                    .ok_or("Too many recurring events")?,
+                None => vec![start],
+            },
+            _ => vec![start],
+        };
+
+        let excluded: Vec<DateTime<Utc>> = event
+            .properties
+            .iter()
+            .filter(|property| property.name == "EXDATE")
+            .flat_map(|property| {
+                property
+                    .value
+                    .split(',')
+                    .filter_map(|value| Moment::parse_value(value.trim(), property, default_tz))
+                    .map(|moment| moment.to_utc())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        for occurrence in occurrences {
+            let start_at = occurrence.to_utc();
+            if excluded.contains(&start_at)
+                || (!is_override && overridden.contains(&(event.uid.clone(), start_at)))
+            {
+                continue;
+            }
+
+            let end_at = occurrence.end(length);
+
+            if start_at < to && end_at > from {
+                busy.push(Busy {
+                    start: start_at,
+                    end: end_at,
+                    tentative,
+                });
+            }
+        }
+    }
+
+    Ok(busy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        value.parse().unwrap()
+    }
+
+    fn calendar(events: &[&str]) -> String {
+        let mut ics = String::from("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n");
+        for event in events {
+            ics.push_str("BEGIN:VEVENT\r\n");
+            ics.push_str(event);
+            ics.push_str("END:VEVENT\r\n");
+        }
+        ics.push_str("END:VCALENDAR\r\n");
+        ics
+    }
+
+    fn starts(busy: &[Busy]) -> Vec<DateTime<Utc>> {
+        let mut starts: Vec<_> = busy.iter().map(|busy| busy.start).collect();
+        starts.sort();
+        starts
+    }
+
+    #[test]
+    fn weekly_rule_skips_exdates_and_moves_overrides() {
+        let ics = calendar(&[
+            "UID:standup\r\n\
+             DTSTART;TZID=Europe/Berlin:20260105T090000\r\n\
+             DTEND;TZID=Europe/Berlin:20260105T100000\r\n\
+             RRULE:FREQ=WEEKLY;BYDAY=MO\r\n\
+             EXDATE;TZID=Europe/Berlin:20260112T090000\r\n",
+            "UID:standup\r\n\
+             RECURRENCE-ID;TZID=Europe/Berlin:20260119T090000\r\n\
+             DTSTART;TZID=Europe/Berlin:20260120T140000\r\n\
+             DTEND;TZID=Europe/Berlin:20260120T150000\r\n",
+        ]);
+
+        let busy = busy_intervals(
+            &ics,
+            Tz::UTC,
+            utc("2026-01-05T00:00:00Z"),
+            utc("2026-02-01T00:00:00Z"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            starts(&busy),
+            vec![
+                utc("2026-01-05T08:00:00Z"),
+                utc("2026-01-20T13:00:00Z"),
+                utc("2026-01-26T08:00:00Z"),
+            ]
+        );
+        assert!(busy.iter().all(|busy| busy.end - busy.start == Duration::hours(1)));
+    }
+
+    #[test]
+    fn folded_lines_are_joined() {
+        let ics = calendar(&[
+            "UID:folded\r\n\
+             SUMMARY:A long\r\n  meeting\r\n\
+             DTSTART;TZID=America/New_\r\n York:20260302T090000\r\n\
+             DURATION:PT\r\n\t30M\r\n",
+        ]);
+
+        let busy = busy_intervals(
+            &ics,
+            Tz::UTC,
+            utc("2026-03-01T00:00:00Z"),
+            utc("2026-03-03T00:00:00Z"),
+        )
+        .unwrap();
+
+        assert_eq!(starts(&busy), vec![utc("2026-03-02T14:00:00Z")]);
+        assert_eq!(busy[0].end, utc("2026-03-02T14:30:00Z"));
+        assert_eq!(
+            unfold("SUMMARY:A long\r\n  meeting\r\nEND:VEVENT"),
+            vec!["SUMMARY:A long meeting", "END:VEVENT"]
+        );
+    }
+
+    #[test]
+    fn old_rules_still_reach_the_window() {
+        let ics = calendar(&[
+            "UID:old\r\n\
+             DTSTART:19800107T120000Z\r\n\
+             DURATION:PT1H\r\n\
+             RRULE:FREQ=WEEKLY;INTERVAL=2\r\n",
+        ]);
+
+        let busy = busy_intervals(
+            &ics,
+            Tz::UTC,
+            utc("2026-06-01T00:00:00Z"),
+            utc("2026-06-15T00:00:00Z"),
+        )
+        .unwrap();
+
+        // Both Mondays, 2026-06-08 is 1211 fortnights after 1980-01-07
+        assert_eq!(starts(&busy), vec![utc("2026-06-08T12:00:00Z")]);
+    }
+
+    #[test]
+    fn occurrences_keep_to_the_window_and_count() {
+        let start = Moment {
+            local: utc("2026-01-01T09:00:00Z").naive_utc(),
+            tz: Tz::UTC,
+            all_day: false,
+        };
+        let rule = Rule::parse("FREQ=DAILY;COUNT=5", &start).unwrap();
+        let mut steps = MAX_RECURRENCE_STEPS;
+
+        let occurrences = rule
+            .occurrences(
+                start,
+                Duration::hours(1),
+                utc("2026-01-03T00:00:00Z"),
+                utc("2026-02-01T00:00:00Z"),
+                &mut steps,
+            )
+            .unwrap();
+
+        let days: Vec<u32> = occurrences.iter().map(|o| o.local.day()).collect();
+        assert_eq!(days, vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn overnight_instances_reaching_into_the_window_count() {
+        let start = Moment {
+            local: utc("2026-01-01T22:00:00Z").naive_utc(),
+            tz: Tz::UTC,
+            all_day: false,
+        };
+        let rule = Rule::parse("FREQ=DAILY", &start).unwrap();
+        let mut steps = MAX_RECURRENCE_STEPS;
+
+        let occurrences = rule
+            .occurrences(
+                start,
+                Duration::hours(4),
+                utc("2026-01-10T00:00:00Z"),
+                utc("2026-01-11T00:00:00Z"),
+                &mut steps,
+            )
+            .unwrap();
+
+        let days: Vec<u32> = occurrences.iter().map(|o| o.local.day()).collect();
+        assert_eq!(days, vec![9, 10]);
+    }
+
+    #[test]
+    fn expansions_are_capped() {
+        let ics = calendar(&[
+            "UID:counted\r\n\
+             DTSTART:17000101T120000Z\r\n\
+             DURATION:PT1H\r\n\
+             RRULE:FREQ=DAILY;COUNT=1000000\r\n",
+        ]);
+
+        let result = busy_intervals(
+            &ics,
+            Tz::UTC,
+            utc("2026-01-01T00:00:00Z"),
+            utc("2026-01-02T00:00:00Z"),
+        );
+
+        assert!(result.is_err());
+    }
+
+    fn date(value: &str) -> NaiveDate {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn yearly_rules_count_from_the_end_in_the_last_week() {
+        assert_eq!(yearly_rule(date("2024-03-31")), "FREQ=YEARLY;BYMONTH=3;BYDAY=-1SU");
+        assert_eq!(yearly_rule(date("2024-03-24")), "FREQ=YEARLY;BYMONTH=3;BYDAY=4SU");
+        assert_eq!(yearly_rule(date("2024-10-06")), "FREQ=YEARLY;BYMONTH=10;BYDAY=1SU");
+        // February's length decides whether the 23rd is in the last week
+        assert_eq!(yearly_rule(date("2024-02-22")), "FREQ=YEARLY;BYMONTH=2;BYDAY=4TH");
+        assert_eq!(yearly_rule(date("2024-02-23")), "FREQ=YEARLY;BYMONTH=2;BYDAY=-1FR");
+        assert_eq!(yearly_rule(date("2023-02-22")), "FREQ=YEARLY;BYMONTH=2;BYDAY=-1WE");
+    }
+
+    #[test]
+    fn vtimezone_follows_berlin_summer_time() {
+        assert_eq!(
+            vtimezone(Tz::Europe__Berlin, 2024),
+            vec![
+                "BEGIN:VTIMEZONE",
+                "TZID:Europe/Berlin",
+                "BEGIN:DAYLIGHT",
+                "DTSTART:20240331T020000",
+                "TZOFFSETFROM:+0100",
+                "TZOFFSETTO:+0200",
+                "TZNAME:CEST",
+                "RRULE:FREQ=YEARLY;BYMONTH=3;BYDAY=-1SU",
+                "END:DAYLIGHT",
+                "BEGIN:STANDARD",
+                "DTSTART:20241027T030000",
+                "TZOFFSETFROM:+0200",
+                "TZOFFSETTO:+0100",
+                "TZNAME:CET",
+                "RRULE:FREQ=YEARLY;BYMONTH=10;BYDAY=-1SU",
+                "END:STANDARD",
+                "END:VTIMEZONE",
+            ]
+        );
+    }
+
+    #[test]
+    fn vtimezone_leaves_sydney_summer_time_in_april() {
+        // Summer time runs over New Year, so the year opens on daylight time
+        assert_eq!(
+            vtimezone(Tz::Australia__Sydney, 2024),
+            vec![
+                "BEGIN:VTIMEZONE",
+                "TZID:Australia/Sydney",
+                "BEGIN:STANDARD",
+                "DTSTART:20240407T030000",
+                "TZOFFSETFROM:+1100",
+                "TZOFFSETTO:+1000",
+                "TZNAME:AEST",
+                "RRULE:FREQ=YEARLY;BYMONTH=4;BYDAY=1SU",
+                "END:STANDARD",
+                "BEGIN:DAYLIGHT",
+                "DTSTART:20241006T020000",
+                "TZOFFSETFROM:+1000",
+                "TZOFFSETTO:+1100",
+                "TZNAME:AEDT",
+                "RRULE:FREQ=YEARLY;BYMONTH=10;BYDAY=1SU",
+                "END:DAYLIGHT",
+                "END:VTIMEZONE",
+            ]
+        );
+    }
+
+    #[test]
+    fn vtimezone_without_transitions_is_one_standard_block() {
+        assert_eq!(
+            vtimezone(Tz::Asia__Tokyo, 2024),
+            vec![
+                "BEGIN:VTIMEZONE",
+                "TZID:Asia/Tokyo",
+                "BEGIN:STANDARD",
+                "DTSTART:19700101T000000",
+                "TZOFFSETFROM:+0900",
+                "TZOFFSETTO:+0900",
+                "TZNAME:JST",
+                "END:STANDARD",
+                "END:VTIMEZONE",
+            ]
+        );
+    }
+
+    #[test]
+    fn long_lines_are_folded_between_characters() {
+        let line = format!("SUMMARY:{}é", "a".repeat(66));
+        assert_eq!(fold_line(&line), format!("SUMMARY:{}\r\n é\r\n", "a".repeat(66)));
+
+        let summary = format!("Team lunch, {}", "planning ".repeat(20).trim_end());
+        let event = Event {
+            uid: "ABCD".to_string(),
+            summary: summary.clone(),
+            description: String::new(),
+            url: "https://example.com/ABCD".to_string(),
+            start: date("2024-06-03").and_hms_opt(12, 0, 0).unwrap(),
+            end: date("2024-06-03").and_hms_opt(13, 0, 0).unwrap(),
+            timezone: Some(Tz::Europe__Berlin),
+            weekly: false,
+        };
+        let ics = super::calendar(&event, utc("2024-06-01T00:00:00Z"));
+
+        assert!(ics.split("\r\n").all(|line| line.len() <= 75));
+        assert!(unfold(&ics).contains(&format!("SUMMARY:{}", escape_text(&summary))));
+    }
+}
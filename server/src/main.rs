@@ -2,8 +2,17 @@ extern crate dotenv;
 
 mod utils;
 
+mod challenge;
+
+mod schedule;
+
+mod icalendar;
+
 mod handlers;
-use handlers::{auth, room, websocket};
+use handlers::{admin, auth, calendar, room, template, websocket};
+
+mod middleware;
+use middleware::{AdminAuth, CsrfGuard, RateLimit, RequireOrigin, CSRF_HEADER};
 
 mod models;
 use models::State;
@@ -15,6 +24,7 @@ use tide::http::headers::HeaderValue;
 use tide::security::CorsMiddleware;
 use tide::security::Origin;
 use tide_websockets::WebSocket;
+use utils::allowed_origins;
 
 #[async_std::main]
 async fn main() -> tide::Result<()> {
@@ -22,8 +32,15 @@ async fn main() -> tide::Result<()> {
 
     let pool = MySqlPool::connect(&env::var("DATABASE_URL")?).await?;
 
+    let state = State::new(pool.clone());
+
+    for limiter in state.rate_limits.all() {
+        println!("Rate limit: {}", limiter.stats().await);
+    }
+
     // Periodic cleanup of expired rooms
     let cleanup_pool = pool.clone();
+    let cleanup_state = state.clone();
     async_std::task::spawn(async move {
         loop {
             async_std::task::sleep(std::time::Duration::from_secs(3600)).await;
@@ -31,30 +48,95 @@ async fn main() -> tide::Result<()> {
                 .execute(&cleanup_pool).await {
                 eprintln!("Room cleanup error (users_of_rooms): {}", e);
             }
+            if let Err(e) = sqlx::query("DELETE FROM room_bans WHERE room_uid IN (SELECT uid FROM rooms WHERE expires_at < NOW())")
+                .execute(&cleanup_pool).await {
+                eprintln!("Room cleanup error (room_bans): {}", e);
+            }
             if let Err(e) = sqlx::query("DELETE FROM rooms WHERE expires_at < NOW()")
                 .execute(&cleanup_pool).await {
                 eprintln!("Room cleanup error (rooms): {}", e);
             }
+            cleanup_state.challenges.prune().await;
+            for limiter in cleanup_state.rate_limits.all() {
+                limiter.prune().await;
+                println!("Rate limit: {}", limiter.stats().await);
+            }
         }
     });
 
-    let mut app = tide::with_state(State::new(pool));
+    // Lets connected clients know as soon as a room's responses close
+    let deadline_state = state.clone();
+    async_std::task::spawn(async move {
+        loop {
+            async_std::task::sleep(std::time::Duration::from_secs(30)).await;
+            if let Err(e) = websocket::announce_deadlines(&deadline_state).await {
+                eprintln!("Deadline announcement error: {}", e);
+            }
+        }
+    });
+
+    let mut app = tide::with_state(state.clone());
+
+    let origins = allowed_origins()?;
 
     let cors = CorsMiddleware::new()
         .allow_methods("GET, POST, DELETE".parse::<HeaderValue>().unwrap())
-        .allow_origin(Origin::from(env::var("FRONTEND_URL")?))
-        .allow_credentials(true);
+        .allow_headers(
+            format!("Content-Type, {}", CSRF_HEADER)
+                .parse::<HeaderValue>()
+                .unwrap(),
+        )
+        .allow_origin(Origin::from(origins.clone()))
+        .allow_credentials(true)
+        .expose_headers("Retry-After".parse::<HeaderValue>().unwrap());
 
     app.with(cors);
+    app.with(CsrfGuard::new(origins.clone()));
 
     app.at("/").get(|_| async { Ok("Hello, world!") });
-    app.at("/api/auth").post(auth::authenticate);
-    app.at("/api/rooms").post(room::create_room);
+    app.at("/api/auth")
+        .with(RateLimit(state.rate_limits.auth.clone()))
+        .post(auth::authenticate);
+    app.at("/api/auth").delete(auth::revoke_all_tokens);
+    app.at("/api/challenge")
+        .with(RateLimit(state.rate_limits.challenge.clone()))
+        .get(room::get_challenge);
+    app.at("/api/rooms")
+        .with(RateLimit(state.rate_limits.create_room.clone()))
+        .post(room::create_room);
     app.at("/api/rooms/:room_uid").get(room::get_room);
     app.at("/api/rooms/:room_uid").delete(room::delete_room);
+    app.at("/api/rooms/:room_uid/best-times").get(room::best_times);
+    app.at("/api/rooms/:room_uid/clone")
+        .with(RateLimit(state.rate_limits.create_room.clone()))
+        .post(room::clone_room);
+    app.at("/api/rooms/:room_uid/event.ics").get(calendar::event_ics);
+    app.at("/api/rooms/:room_uid/import-ics")
+        .with(RateLimit(state.rate_limits.import_ics.clone()))
+        .post(calendar::import_ics);
+    app.at("/api/rooms/:room_uid/read-only-token")
+        .post(room::create_read_only_token);
+    app.at("/api/rooms/:room_uid/read-only-token")
+        .delete(room::delete_read_only_token);
     app.at("/api/og/:room_uid").get(room::og_page);
+    app.at("/api/templates").get(template::list_templates);
+    app.at("/api/templates").post(template::save_template);
+    app.at("/api/templates").delete(template::delete_template);
+
+    let mut admin_api = tide::with_state(state.clone());
+    admin_api.with(AdminAuth::from_env());
+    admin_api.at("/stats").get(admin::stats);
+    admin_api.at("/rooms").get(admin::list_rooms);
+    admin_api.at("/rooms/:room_uid").get(admin::get_room);
+    admin_api.at("/rooms/:room_uid").delete(admin::delete_room);
+    admin_api.at("/rooms/:room_uid/extend").post(admin::extend_room);
+    admin_api.at("/challenge").post(admin::set_challenge_difficulty);
+    admin_api.at("/users/:user_uid/tokens").delete(admin::revoke_user_tokens);
+    app.at("/api/admin").nest(admin_api);
 
     app.at("/api/ws/:room_uid")
+        .with(RequireOrigin::new(origins))
+        .with(RateLimit(state.rate_limits.websocket.clone()))
         .with(WebSocket::new(websocket::connect_websocket))
         .get(|_| async move { Ok("this was not a websocket request") });
 
@@ -0,0 +1,368 @@
+use crate::models::State;
+use crate::utils::get_user_uid_from_cookie;
+
+use async_std::sync::Mutex;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tide::http::headers::{AUTHORIZATION, ORIGIN, RETRY_AFTER};
+use tide::http::Method;
+use tide::prelude::*;
+use tide::{Middleware, Next, Request, Response, StatusCode};
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket limiter, one bucket per peer address and one per user uid.
+///
+/// Configured with `<requests>/<seconds>`, e.g. `10/3600` allows a burst of
+/// 10 requests that refills completely over an hour.
+pub struct RateLimiter {
+    pub name: &'static str,
+    capacity: f64,
+    period: Duration,
+    buckets: Mutex<HashMap<String, Bucket>>,
+    allowed: AtomicU64,
+    limited: AtomicU64,
+}
+
+impl RateLimiter {
+    /// Reads the limit from `env_key`, falling back to the given default.
+    /// Setting the variable to `off` disables the limiter.
+    pub fn from_env(name: &'static str, env_key: &str, requests: u32, seconds: u64) -> Self {
+        let (capacity, period) = match std::env::var(env_key) {
+            Ok(value) if value.trim() == "off" => (0.0, Duration::ZERO),
+            Ok(value) => match parse_limit(&value) {
+                Some(limit) => limit,
+                None => {
+                    println!("Invalid {}={:?}, using default", env_key, value);
+                    (requests as f64, Duration::from_secs(seconds))
+                }
+            },
+            Err(_) => (requests as f64, Duration::from_secs(seconds)),
+        };
+
+        Self {
+            name,
+            capacity,
+            period,
+            buckets: Default::default(),
+            allowed: AtomicU64::new(0),
+            limited: AtomicU64::new(0),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0.0 && !self.period.is_zero()
+    }
+
+    fn refill_per_sec(&self) -> f64 {
+        self.capacity / self.period.as_secs_f64()
+    }
+
+    /// Takes one token from every bucket in `keys`, or none of them.
+    /// On refusal returns how long until all buckets have a token again.
+    async fn try_acquire(&self, keys: &[String]) -> Result<(), Duration> {
+        let now = Instant::now();
+        let refill_per_sec = self.refill_per_sec();
+        let mut buckets = self.buckets.lock().await;
+
+        let mut wait_secs: f64 = 0.0;
+        for key in keys {
+            let bucket = buckets.entry(key.clone()).or_insert(Bucket {
+                tokens: self.capacity,
+                updated: now,
+            });
+            let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * refill_per_sec).min(self.capacity);
+            bucket.updated = now;
+
+            if bucket.tokens < 1.0 {
+                wait_secs = wait_secs.max((1.0 - bucket.tokens) / refill_per_sec);
+            }
+        }
+
+        if wait_secs > 0.0 {
+            self.limited.fetch_add(1, Ordering::Relaxed);
+            return Err(Duration::from_secs_f64(wait_secs));
+        }
+
+        for key in keys {
+            if let Some(bucket) = buckets.get_mut(key) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        self.allowed.fetch_add(1, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /// Drops buckets that have refilled completely, they carry no state.
+    pub async fn prune(&self) {
+        if !self.is_enabled() {
+            return;
+        }
+        let now = Instant::now();
+        let refill_per_sec = self.refill_per_sec();
+        self.buckets.lock().await.retain(|_, bucket| {
+            let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens + elapsed * refill_per_sec < self.capacity
+        });
+    }
+
+    pub async fn stats(&self) -> serde_json::Value {
+        json!({
+            "name": self.name,
+            "enabled": self.is_enabled(),
+            "requests": self.capacity,
+            "period_secs": self.period.as_secs(),
+            "allowed": self.allowed.load(Ordering::Relaxed),
+            "limited": self.limited.load(Ordering::Relaxed),
+            "tracked_keys": self.buckets.lock().await.len(),
+        })
+    }
+}
+
+fn parse_limit(value: &str) -> Option<(f64, Duration)> {
+    let (requests, seconds) = value.trim().split_once('/')?;
+    let requests: u32 = requests.trim().parse().ok()?;
+    let seconds: u64 = seconds.trim().parse().ok()?;
+    if requests == 0 || seconds == 0 {
+        return None;
+    }
+    Some((requests as f64, Duration::from_secs(seconds)))
+}
+
+/// Header the reverse proxy in front of the API puts the client's address in,
+/// from `CLIENT_IP_HEADER`, e.g. `X-Forwarded-For` or `X-Real-IP`. Behind a
+/// proxy every connection comes from the proxy's own address, so without it
+/// all clients would share one bucket. Only set it when the proxy overwrites
+/// or appends to the header, clients can send anything in it otherwise.
+fn client_ip_header() -> Option<&'static str> {
+    static HEADER: OnceLock<Option<String>> = OnceLock::new();
+    HEADER
+        .get_or_init(|| {
+            std::env::var("CLIENT_IP_HEADER")
+                .ok()
+                .map(|header| header.trim().to_string())
+                .filter(|header| !header.is_empty())
+        })
+        .as_deref()
+}
+
+/// Client address without the port, so reconnecting clients share a bucket.
+/// Taken from `client_ip_header` when configured, the last entry being the
+/// one our proxy added.
+fn peer_ip(req: &Request<State>) -> Option<String> {
+    let forwarded = client_ip_header()
+        .and_then(|header| req.header(header))
+        .and_then(|values| values.last().as_str().rsplit(',').next())
+        .map(|ip| ip.trim().to_string())
+        .filter(|ip| !ip.is_empty());
+    if forwarded.is_some() {
+        return forwarded;
+    }
+
+    let peer_addr = req.peer_addr()?;
+    Some(match peer_addr.parse::<SocketAddr>() {
+        Ok(addr) => addr.ip().to_string(),
+        Err(_) => peer_addr.to_string(),
+    })
+}
+
+#[derive(Clone)]
+pub struct RateLimits {
+    pub create_room: Arc<RateLimiter>,
+    pub auth: Arc<RateLimiter>,
+    pub websocket: Arc<RateLimiter>,
+    pub challenge: Arc<RateLimiter>,
+    pub import_ics: Arc<RateLimiter>,
+}
+
+impl RateLimits {
+    pub fn from_env() -> Self {
+        Self {
+            create_room: Arc::new(RateLimiter::from_env(
+                "create_room",
+                "RATE_LIMIT_CREATE_ROOM",
+                20,
+                3600,
+            )),
+            auth: Arc::new(RateLimiter::from_env("auth", "RATE_LIMIT_AUTH", 60, 60)),
+            websocket: Arc::new(RateLimiter::from_env(
+                "websocket",
+                "RATE_LIMIT_WEBSOCKET",
+                30,
+                60,
+            )),
+            challenge: Arc::new(RateLimiter::from_env(
+                "challenge",
+                "RATE_LIMIT_CHALLENGE",
+                30,
+                60,
+            )),
+            import_ics: Arc::new(RateLimiter::from_env(
+                "import_ics",
+                "RATE_LIMIT_IMPORT_ICS",
+                20,
+                60,
+            )),
+        }
+    }
+
+    pub fn all(&self) -> [&Arc<RateLimiter>; 5] {
+        [
+            &self.create_room,
+            &self.auth,
+            &self.websocket,
+            &self.challenge,
+            &self.import_ics,
+        ]
+    }
+}
+
+pub struct RateLimit(pub Arc<RateLimiter>);
+
+#[tide::utils::async_trait]
+impl Middleware<State> for RateLimit {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let limiter = &self.0;
+        if !limiter.is_enabled() {
+            return Ok(next.run(req).await);
+        }
+
+        let mut keys = Vec::with_capacity(2);
+        if let Some(ip) = peer_ip(&req) {
+            keys.push(format!("ip:{}", ip));
+        }
+        if let Some(user_uid) = get_user_uid_from_cookie(&req).await {
+            keys.push(format!("user:{}", user_uid));
+        }
+
+        if let Err(retry_after) = limiter.try_acquire(&keys).await {
+            println!("Rate limited {} for {}", limiter.name, keys.join(", "));
+
+            let mut response = Response::new(StatusCode::TooManyRequests);
+            response.insert_header(RETRY_AFTER, retry_after.as_secs_f64().ceil().to_string());
+            return Ok(response);
+        }
+
+        Ok(next.run(req).await)
+    }
+}
+
+fn is_allowed_origin(req: &Request<State>, allowed: &[String]) -> bool {
+    match req.header(ORIGIN) {
+        Some(origin) => allowed.iter().any(|o| o == origin.last().as_str()),
+        None => false,
+    }
+}
+
+/// Browsers don't apply CORS to websocket upgrades, so without this check any
+/// site could open a socket with the user's cookie.
+pub struct RequireOrigin {
+    allowed: Vec<String>,
+}
+
+impl RequireOrigin {
+    pub fn new(allowed: Vec<String>) -> Self {
+        Self { allowed }
+    }
+}
+
+#[tide::utils::async_trait]
+impl Middleware<State> for RequireOrigin {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        if !is_allowed_origin(&req, &self.allowed) {
+            println!(
+                "Refusing {} from origin {:?}",
+                req.url().path(),
+                req.header(ORIGIN).map(|origin| origin.last().as_str().to_string())
+            );
+            return Ok(Response::new(StatusCode::Forbidden));
+        }
+
+        Ok(next.run(req).await)
+    }
+}
+
+/// Header the frontend sends on every state-changing request. A cross-site
+/// form can't set it, and a cross-site script can't without passing CORS.
+pub const CSRF_HEADER: &str = "X-Requested-With";
+
+/// Guards cookie-authenticated, state-changing requests against CSRF by
+/// requiring `CSRF_HEADER`, and an allowed `Origin` whenever one is sent.
+pub struct CsrfGuard {
+    allowed: Vec<String>,
+}
+
+impl CsrfGuard {
+    pub fn new(allowed: Vec<String>) -> Self {
+        Self { allowed }
+    }
+}
+
+#[tide::utils::async_trait]
+impl Middleware<State> for CsrfGuard {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let is_safe_method = matches!(req.method(), Method::Get | Method::Head | Method::Options);
+
+        // Without the cookie there's no ambient authority to forge
+        if is_safe_method || req.cookie("auth_token").is_none() {
+            return Ok(next.run(req).await);
+        }
+
+        if req.header(CSRF_HEADER).is_none()
+            || (req.header(ORIGIN).is_some() && !is_allowed_origin(&req, &self.allowed))
+        {
+            println!("Refusing {} {} without CSRF protection", req.method(), req.url().path());
+            return Ok(Response::new(StatusCode::Forbidden));
+        }
+
+        Ok(next.run(req).await)
+    }
+}
+
+/// Protects the admin namespace with `Authorization: Bearer <ADMIN_KEY>`.
+/// Without `ADMIN_KEY` set the whole namespace answers 404.
+pub struct AdminAuth {
+    key_hash: Option<Vec<u8>>,
+}
+
+impl AdminAuth {
+    pub fn from_env() -> Self {
+        Self {
+            key_hash: std::env::var("ADMIN_KEY")
+                .ok()
+                .filter(|key| !key.is_empty())
+                .map(|key| Sha256::digest(key.as_bytes()).to_vec()),
+        }
+    }
+}
+
+#[tide::utils::async_trait]
+impl Middleware<State> for AdminAuth {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let Some(key_hash) = &self.key_hash else {
+            return Ok(Response::new(StatusCode::NotFound));
+        };
+
+        // Comparing digests keeps the comparison time independent of the key
+        let given_hash = req
+            .header(AUTHORIZATION)
+            .and_then(|value| value.last().as_str().strip_prefix("Bearer "))
+            .map(|key| Sha256::digest(key.trim().as_bytes()).to_vec());
+
+        if given_hash.as_ref() != Some(key_hash) {
+            println!("Refusing admin request to {}", req.url().path());
+            return Ok(Response::new(StatusCode::Unauthorized));
+        }
+
+        Ok(next.run(req).await)
+    }
+}
@@ -1,4 +1,9 @@
+use crate::challenge::{ChallengeSolution, Challenges};
+use crate::middleware::RateLimits;
+use crate::schedule::Preference;
+
 use async_std::sync::Mutex;
+use chrono_tz::Tz;
 use serde::Deserialize;
 use sqlx::MySql;
 use sqlx::Pool;
@@ -10,10 +15,18 @@ use tide_websockets::WebSocketConnection;
 type RoomUID = String;
 type UserUID = String;
 
+/// Connections opened with a read-only token are keyed by this prefix plus a
+/// random id, so they never collide with a real user's uid.
+pub const READ_ONLY_VIEWER_PREFIX: &str = "view:";
+
 #[derive(Clone)]
 pub struct State {
     pub db_pool: Pool<MySql>,
     pub rooms: Arc<Mutex<HashMap<RoomUID, HashMap<UserUID, WebSocketConnection>>>>,
+    /// Zones connections asked to see the room in, see `ViewerGrid`
+    pub viewer_timezones: Arc<Mutex<HashMap<(RoomUID, UserUID), Tz>>>,
+    pub rate_limits: RateLimits,
+    pub challenges: Challenges,
 }
 
 impl State {
@@ -21,6 +34,9 @@ impl State {
         Self {
             db_pool,
             rooms: Default::default(),
+            viewer_timezones: Default::default(),
+            rate_limits: RateLimits::from_env(),
+            challenges: Challenges::from_env(),
         }
     }
 }
@@ -34,6 +50,8 @@ pub struct UserOfRoom {
     pub is_owner: bool,
     pub is_absent: bool,
     pub absent_reason: String,
+    pub is_pending: bool,
+    pub is_required: bool,
 }
 
 #[allow(dead_code)]
@@ -44,19 +62,110 @@ pub struct Room {
     pub event_name: String,
     pub dates: String,
     pub days_of_week: String,
-    pub time_min: u8,
-    pub time_max: u8,
+    pub start_minute: u16,
+    pub end_minute: u16,
     pub slot_length: u8,
     pub schedule: String,
+    pub if_needed: String,
     pub participants: String,
     pub timezone: String,
+    pub read_only_token: Option<String>,
+    pub require_approval: bool,
+    pub finalized: Option<String>,
+    /// JSON `Vec<DayWindow>`, one per column, when columns have their own hours
+    pub day_windows: Option<String>,
+    /// JSON `Vec<String>`, the choices of a poll room
+    pub options: Option<String>,
+    /// After this only the owner can still change anything
+    pub response_deadline: Option<time_new::OffsetDateTime>,
     pub expires_at: time_new::OffsetDateTime,
 }
 
-#[derive(Serialize, Deserialize)]
+#[cfg(test)]
+impl Room {
+    /// A room over no dates from 9:00 to 17:00 in UTC with hour slots and no
+    /// one in it, for tests to fill in what they need
+    pub fn for_test() -> Self {
+        Self {
+            uid: "TEST".to_string(),
+            schedule_type: 0,
+            event_name: "Lunch".to_string(),
+            dates: "[]".to_string(),
+            days_of_week: "[]".to_string(),
+            start_minute: 9 * 60,
+            end_minute: 17 * 60,
+            slot_length: 60,
+            schedule: "[]".to_string(),
+            if_needed: "[]".to_string(),
+            participants: "[]".to_string(),
+            timezone: "UTC".to_string(),
+            read_only_token: None,
+            require_approval: false,
+            finalized: None,
+            day_windows: None,
+            options: None,
+            response_deadline: None,
+            expires_at: time_new::OffsetDateTime::UNIX_EPOCH,
+        }
+    }
+}
+
+/// The slot range the owner settled on, `end_slot` is exclusive
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Finalized {
+    pub day_index: usize,
+    pub start_slot: usize,
+    pub end_slot: usize,
+    /// Whether `editSchedule` is refused while finalized
+    pub lock_edits: bool,
+}
+
+/// The window of every column. Clients that only know whole hours send and
+/// read the hours, the minutes take precedence when given.
+#[derive(Serialize, Deserialize, Default)]
 pub struct TimeRange {
+    #[serde(default)]
     pub from_hour: u8,
+    #[serde(default)]
     pub to_hour: u8,
+    #[serde(default)]
+    pub from_minute: Option<u16>,
+    #[serde(default)]
+    pub to_minute: Option<u16>,
+}
+
+/// A single column's hours, in minutes like `Room::start_minute`
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+pub struct DayWindow {
+    pub start_minute: u16,
+    pub end_minute: u16,
+}
+
+impl TimeRange {
+    /// Hours are rounded outwards so they still cover the whole window
+    pub fn from_minutes(from_minute: u16, to_minute: u16) -> Self {
+        Self {
+            from_hour: (from_minute / 60) as u8,
+            to_hour: to_minute.div_ceil(60) as u8,
+            from_minute: Some(from_minute),
+            to_minute: Some(to_minute),
+        }
+    }
+
+    pub fn start_minute(&self) -> u16 {
+        self.from_minute.unwrap_or(u16::from(self.from_hour) * 60)
+    }
+
+    pub fn end_minute(&self) -> u16 {
+        self.to_minute.unwrap_or(u16::from(self.to_hour) * 60)
+    }
+
+    pub fn day_window(&self) -> DayWindow {
+        DayWindow {
+            start_minute: self.start_minute(),
+            end_minute: self.end_minute(),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -71,15 +180,68 @@ pub struct GetRoomRes {
     pub schedule_type: u8,
     pub dates: Vec<String>,
     pub days_of_week: Vec<u8>,
-    pub slot_length: u8,
+    /// Left out for polls, whose options have no times
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slot_length: Option<u8>,
     pub user_schedule: Vec<Vec<bool>>,
+    pub user_preferences: Vec<Vec<u8>>,
     pub others_schedule: Vec<Vec<Vec<usize>>>,
+    pub others_if_needed: Vec<Vec<Vec<usize>>>,
     pub user_name: String,
     pub others_names: Vec<String>,
-    pub time_range: TimeRange,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_range: Option<TimeRange>,
+    /// Each column's own hours, if they differ between columns
+    pub day_windows: Option<Vec<TimeRange>>,
+    /// Week of each column in multi-week rooms, counted from the first date
+    pub column_weeks: Vec<Option<usize>>,
+    /// The choices of a poll room, one column each
+    pub options: Vec<String>,
     pub is_owner: bool,
     pub absent_reasons: Vec<Option<String>>,
+    /// Aligned with `absent_reasons`, the current user first
+    pub required: Vec<bool>,
+    pub timezone: String,
+    pub read_only: bool,
+    pub read_only_token: Option<String>,
+    pub require_approval: bool,
+    pub is_pending: bool,
+    pub pending_names: Vec<String>,
+    pub finalized: Option<Finalized>,
+    pub response_deadline: Option<String>,
+    /// The deadline has passed, only the owner can still edit
+    pub responses_closed: bool,
+    /// UTC offset in minutes at the start of each column's window
+    pub utc_offsets: Vec<Option<i32>>,
+    /// Slots a DST change skips or repeats on the wall clock
+    pub dst_slots: Vec<DstSlot>,
+    pub viewer: Option<ViewerGrid>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DstKind {
+    Skipped,
+    Repeated,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DstSlot {
+    pub day_index: usize,
+    pub slot: usize,
+    pub kind: DstKind,
+}
+
+/// Present when dates and grids are shifted into the viewer's zone. Columns are
+/// then the viewer's dates and rows the viewer's times, edits are taken in the
+/// same shape. `utc_offsets` and `dst_slots` follow, `finalized` stays in room time.
+#[derive(Serialize, Deserialize)]
+pub struct ViewerGrid {
     pub timezone: String,
+    /// `HH:MM` of every row
+    pub slot_labels: Vec<String>,
+    /// `false` where no room slot falls, such cells are ignored on edit
+    pub has_slot: Vec<Vec<bool>>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -87,6 +249,16 @@ pub struct GetRoomRes {
 pub enum ScheduleDates {
     Dates(Vec<String>),
     DaysOfWeek(Vec<u8>),
+    /// The chosen weekdays of `week_count` weeks from `start_date` on, kept as
+    /// plain dates
+    Weeks {
+        start_date: String,
+        days_of_week: Vec<u8>,
+        week_count: u8,
+    },
+    /// A poll on text choices, each a column of a single slot answered yes,
+    /// maybe or no like available, if needed or unavailable
+    Options { options: Vec<String> },
 }
 
 #[derive(Serialize, Deserialize)]
@@ -94,10 +266,20 @@ pub struct CreateRoomReq {
     pub event_name: String,
     pub schedule_type: u8, // NOTE: I want to use an enum but sqlx nor TS+serde work well
     pub dates: ScheduleDates,
+    /// Polls go without `slot_length` and `time_range`
+    #[serde(default)]
     pub slot_length: u8,
-    pub schedule: Vec<Vec<bool>>,
+    pub schedule: Vec<Vec<Preference>>,
+    #[serde(default)]
     pub time_range: TimeRange,
+    /// Hours per date or weekday, in the same order, overriding `time_range`
+    #[serde(default)]
+    pub day_windows: Option<Vec<TimeRange>>,
     pub timezone: String,
+    #[serde(default)]
+    pub require_approval: bool,
+    #[serde(default)]
+    pub challenge: Option<ChallengeSolution>,
 }
 
 #[derive(Serialize)]
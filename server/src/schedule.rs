@@ -0,0 +1,1241 @@
+use crate::models::{DayWindow, DstKind, DstSlot, Finalized, Room};
+
+use chrono::{
+    DateTime, Datelike, Duration, LocalResult, NaiveDate, Offset, TimeZone, Timelike, Utc,
+};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Participant indices per cell, `schedule[day][slot]`
+pub type Grid = Vec<Vec<Vec<usize>>>;
+
+/// Preference levels of a cell. The room's schedule lists everyone at either
+/// level, its `if_needed` grid only those who'd rather not.
+pub const UNAVAILABLE: u8 = 0;
+pub const IF_NEEDED: u8 = 1;
+pub const AVAILABLE: u8 = 2;
+
+/// A cell as clients send it, older clients only know available or not
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(untagged)]
+pub enum Preference {
+    Flag(bool),
+    Level(u8),
+}
+
+impl Preference {
+    pub fn level(self) -> u8 {
+        match self {
+            Preference::Flag(true) => AVAILABLE,
+            Preference::Flag(false) => UNAVAILABLE,
+            Preference::Level(level) => level.min(AVAILABLE),
+        }
+    }
+}
+
+/// Resizes `grid` to the shape of `like`, padding with empty cells.
+/// Rooms from before `if_needed` existed store it as `[]`.
+pub fn conform(mut grid: Grid, like: &Grid) -> Grid {
+    grid.resize(like.len(), Vec::new());
+    for (day, like_day) in grid.iter_mut().zip(like) {
+        day.resize(like_day.len(), Vec::new());
+    }
+    grid
+}
+
+/// Sets `p_idx`'s level in every cell, `preferences` missing cells count as unavailable
+pub fn set_preferences(
+    schedule: &mut Grid,
+    if_needed: &mut Grid,
+    p_idx: usize,
+    preferences: &[Vec<Preference>],
+) {
+    for (i, (row, if_needed_row)) in schedule.iter_mut().zip(if_needed.iter_mut()).enumerate() {
+        for (j, (slot, if_needed_slot)) in row.iter_mut().zip(if_needed_row.iter_mut()).enumerate() {
+            slot.retain(|&idx| idx != p_idx);
+            if_needed_slot.retain(|&idx| idx != p_idx);
+
+            let level = preferences
+                .get(i)
+                .and_then(|row| row.get(j))
+                .map(|preference| preference.level())
+                .unwrap_or(UNAVAILABLE);
+            if level >= IF_NEEDED {
+                slot.push(p_idx);
+            }
+            if level == IF_NEEDED {
+                if_needed_slot.push(p_idx);
+            }
+        }
+    }
+}
+
+/// `p_idx`'s level in every cell, all unavailable for `None`
+pub fn preferences_of(schedule: &Grid, if_needed: &Grid, p_idx: Option<usize>) -> Vec<Vec<u8>> {
+    schedule
+        .iter()
+        .enumerate()
+        .map(|(i, row)| {
+            row.iter()
+                .enumerate()
+                .map(|(j, slot)| {
+                    let Some(p_idx) = p_idx.filter(|p_idx| slot.contains(p_idx)) else {
+                        return UNAVAILABLE;
+                    };
+                    let is_if_needed = if_needed
+                        .get(i)
+                        .and_then(|row| row.get(j))
+                        .is_some_and(|slot| slot.contains(&p_idx));
+                    if is_if_needed {
+                        IF_NEEDED
+                    } else {
+                        AVAILABLE
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Drops `p_idx` from every cell and shifts the indices after it down
+pub fn remove_participant(grid: &mut Grid, p_idx: usize) {
+    for slot in grid.iter_mut().flatten() {
+        slot.retain(|&idx| idx != p_idx);
+        for idx in slot.iter_mut() {
+            if *idx > p_idx {
+                *idx -= 1;
+            }
+        }
+    }
+}
+
+pub const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// The hours a grid column covers, in minutes from midnight of its date.
+/// Overnight windows run past `MINUTES_PER_DAY` into the next date, which
+/// the column still belongs to.
+#[derive(Clone, Copy, PartialEq)]
+pub struct TimeWindow {
+    pub start_minute: u32,
+    pub end_minute: u32,
+    pub slot_length: u32,
+}
+
+impl TimeWindow {
+    /// A poll option's column, a single slot at no time of day
+    pub const OPTION: Self = Self {
+        start_minute: 0,
+        end_minute: 0,
+        slot_length: 0,
+    };
+
+    /// An `end_minute` at or before `start_minute` wraps past midnight
+    pub fn new(start_minute: u32, end_minute: u32, slot_length: u32) -> Self {
+        let end_minute = if end_minute <= start_minute {
+            end_minute + MINUTES_PER_DAY
+        } else {
+            end_minute
+        };
+        Self {
+            start_minute,
+            end_minute,
+            slot_length,
+        }
+    }
+
+    pub fn from_room(room: &Room) -> Self {
+        Self::new(
+            u32::from(room.start_minute),
+            u32::from(room.end_minute),
+            u32::from(room.slot_length),
+        )
+    }
+
+    pub fn length(&self) -> u32 {
+        self.end_minute - self.start_minute
+    }
+
+    pub fn slot_count(&self) -> usize {
+        if *self == Self::OPTION {
+            return 1;
+        }
+        if self.slot_length == 0 {
+            return 0;
+        }
+        (self.length() / self.slot_length) as usize
+    }
+
+    /// Minutes from the column's midnight to the start of `slot`
+    pub fn slot_start(&self, slot: usize) -> u32 {
+        self.start_minute + slot as u32 * self.slot_length
+    }
+}
+
+/// Every column's window: the room's per-day windows if it has them,
+/// otherwise its one window repeated. Poll options are a slot each.
+pub fn column_windows(room: &Room) -> Result<Vec<TimeWindow>, serde_json::Error> {
+    if room.schedule_type == 3 {
+        return Ok(vec![TimeWindow::OPTION; poll_options_of(room)?.len()]);
+    }
+    let slot_length = u32::from(room.slot_length);
+    let day_windows: Option<Vec<DayWindow>> = match &room.day_windows {
+        Some(day_windows) => serde_json::from_str(day_windows)?,
+        None => None,
+    };
+    if let Some(day_windows) = day_windows {
+        return Ok(day_windows
+            .into_iter()
+            .map(|window| {
+                TimeWindow::new(
+                    u32::from(window.start_minute),
+                    u32::from(window.end_minute),
+                    slot_length,
+                )
+            })
+            .collect());
+    }
+
+    let column_count = match room.schedule_type {
+        1 => serde_json::from_str::<Vec<serde_json::Value>>(&room.days_of_week)?.len(),
+        _ => serde_json::from_str::<Vec<serde_json::Value>>(&room.dates)?.len(),
+    };
+    Ok(vec![TimeWindow::from_room(room); column_count])
+}
+
+/// A grid without anyone in it, each column as long as its own window
+pub fn empty_grid(windows: &[TimeWindow]) -> Grid {
+    windows
+        .iter()
+        .map(|window| vec![Vec::new(); window.slot_count()])
+        .collect()
+}
+
+/// Dates rooms store the client's `Date.toDateString()`, e.g. `Tue May 28 2024`
+pub const ROOM_DATE_FORMAT: &str = "%a %b %d %Y";
+
+pub fn parse_room_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date, ROOM_DATE_FORMAT).ok()
+}
+
+/// First date on or after `from` falling on `weekday`, 0 being Sunday
+pub fn next_weekday(from: NaiveDate, weekday: u8) -> NaiveDate {
+    let days_ahead =
+        (i64::from(weekday) - i64::from(from.weekday().num_days_from_sunday())).rem_euclid(7);
+    from + Duration::days(days_ahead)
+}
+
+/// Longest a multi-week room can run
+pub const MAX_WEEKS: u8 = 26;
+
+/// Dates of `days_of_week` in each of `week_count` weeks from `start_date`,
+/// in the room date format. `None` if any of it is out of range.
+pub fn week_dates(start_date: &str, days_of_week: &[u8], week_count: u8) -> Option<Vec<String>> {
+    let start = parse_room_date(start_date)?;
+    if days_of_week.is_empty()
+        || days_of_week.iter().any(|&weekday| weekday > 6)
+        || week_count == 0
+        || week_count > MAX_WEEKS
+    {
+        return None;
+    }
+
+    Some(
+        (0..i64::from(week_count) * 7)
+            .map(|offset| start + Duration::days(offset))
+            .filter(|date| days_of_week.contains(&(date.weekday().num_days_from_sunday() as u8)))
+            .map(|date| date.format(ROOM_DATE_FORMAT).to_string())
+            .collect(),
+    )
+}
+
+/// Most options a poll room can have
+pub const MAX_POLL_OPTIONS: usize = 50;
+pub const MAX_POLL_OPTION_LENGTH: usize = 200;
+
+/// `options` trimmed, `None` unless there's at least one and they're all
+/// distinct, non-empty and short enough
+pub fn poll_options(options: Vec<String>) -> Option<Vec<String>> {
+    let options: Vec<String> = options
+        .into_iter()
+        .map(|option| option.trim().to_string())
+        .collect();
+    let distinct: BTreeSet<&String> = options.iter().collect();
+    let valid = !options.is_empty()
+        && options.len() <= MAX_POLL_OPTIONS
+        && distinct.len() == options.len()
+        && options
+            .iter()
+            .all(|option| !option.is_empty() && option.len() <= MAX_POLL_OPTION_LENGTH);
+    valid.then_some(options)
+}
+
+/// The options of a poll room, one column each, empty for other rooms
+pub fn poll_options_of(room: &Room) -> Result<Vec<String>, serde_json::Error> {
+    match &room.options {
+        Some(options) => serde_json::from_str(options),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Where each of the `to` options was among the `from` ones, matched by text
+pub fn option_columns(from: &[String], to: &[String]) -> Vec<Option<usize>> {
+    to.iter()
+        .map(|option| from.iter().position(|old| old == option))
+        .collect()
+}
+
+/// `grid`'s columns rearranged as `option_columns` says, new ones left with
+/// an empty slot
+pub fn select_columns(grid: &Grid, columns: &[Option<usize>]) -> Grid {
+    columns
+        .iter()
+        .map(|column| {
+            column
+                .and_then(|column| grid.get(column).cloned())
+                .unwrap_or_else(|| vec![Vec::new()])
+        })
+        .collect()
+}
+
+/// The week each date falls in, counting seven-day weeks from `first`
+pub fn column_weeks(first: NaiveDate, dates: &[Option<NaiveDate>]) -> Vec<Option<usize>> {
+    dates
+        .iter()
+        .map(|date| Some(((*date)? - first).num_days().max(0) as usize / 7))
+        .collect()
+}
+
+/// Copies the columns of `from_week` onto the same weekday of each week in
+/// `to_weeks`, or of every other week without it, slot by slot at the same
+/// time of day. Target slots without a counterpart keep their level.
+pub fn copy_week(
+    preferences: &mut [Vec<u8>],
+    dates: &[Option<NaiveDate>],
+    windows: &[TimeWindow],
+    from_week: usize,
+    to_weeks: Option<&[usize]>,
+) {
+    let Some(first) = dates.iter().copied().flatten().min() else {
+        return;
+    };
+    let weeks = column_weeks(first, dates);
+    let week_of = |day_index: usize| weeks.get(day_index).copied().flatten();
+    let weekday_of = |day_index: usize| {
+        dates.get(day_index).copied().flatten().map(|date| date.weekday())
+    };
+
+    for target in 0..preferences.len() {
+        let is_target = |week: usize| {
+            week != from_week && to_weeks.is_none_or(|to_weeks| to_weeks.contains(&week))
+        };
+        if !week_of(target).is_some_and(is_target) {
+            continue;
+        }
+        let Some(source) = (0..preferences.len()).find(|&day_index| {
+            week_of(day_index) == Some(from_week) && weekday_of(day_index) == weekday_of(target)
+        }) else {
+            continue;
+        };
+        let (Some(from), Some(to)) = (windows.get(source), windows.get(target)) else {
+            continue;
+        };
+
+        for slot in 0..preferences[target].len() {
+            let minute = to.slot_start(slot);
+            let source_slot = (minute >= from.start_minute
+                && (minute - from.start_minute).is_multiple_of(from.slot_length))
+            .then(|| ((minute - from.start_minute) / from.slot_length) as usize);
+            let level = source_slot.and_then(|slot| preferences[source].get(slot).copied());
+            if let Some(level) = level {
+                preferences[target][slot] = level;
+            }
+        }
+    }
+}
+
+/// The calendar date of every column. Weekly rooms get each weekday's next
+/// occurrence from `today` on.
+pub fn column_dates(room: &Room, today: NaiveDate) -> Result<Vec<Option<NaiveDate>>, serde_json::Error> {
+    if room.schedule_type == 3 {
+        return Ok(vec![None; poll_options_of(room)?.len()]);
+    }
+    Ok(if room.schedule_type == 1 {
+        let days_of_week: Vec<u8> = serde_json::from_str(&room.days_of_week)?;
+        days_of_week
+            .into_iter()
+            .map(|weekday| Some(next_weekday(today, weekday)))
+            .collect()
+    } else {
+        let dates: Vec<String> = serde_json::from_str(&room.dates)?;
+        dates.iter().map(|date| parse_room_date(date)).collect()
+    })
+}
+
+/// Every slot's start on a minute scale shared by all rooms, `None` where the
+/// column's date can't be read, like a poll's. Weekly rooms count within the
+/// week, so the same weekday and time line up however the columns are ordered.
+pub fn slot_times(room: &Room) -> Result<Vec<Vec<Option<i64>>>, serde_json::Error> {
+    const WEEK: i64 = 7 * MINUTES_PER_DAY as i64;
+    // A Sunday, so weekly columns land on the first week
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 4).unwrap_or_default();
+    let dates = column_dates(room, epoch)?;
+
+    Ok(column_windows(room)?
+        .iter()
+        .enumerate()
+        .map(|(day_index, window)| {
+            (0..window.slot_count())
+                .map(|slot| {
+                    let date = dates.get(day_index).copied().flatten()?;
+                    let minute = (date - epoch).num_days() * i64::from(MINUTES_PER_DAY)
+                        + i64::from(window.slot_start(slot));
+                    Some(if room.schedule_type == 1 {
+                        minute.rem_euclid(WEEK)
+                    } else {
+                        minute
+                    })
+                })
+                .collect()
+        })
+        .collect())
+}
+
+/// How merging finer slots into a coarser one decides who's free in it
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeRule {
+    /// Free for the whole of the new slot, at their lowest level in it
+    #[default]
+    All,
+    /// Free for any part of it, at their highest level in it
+    Any,
+}
+
+/// Slot start times of a layout, see `slot_times`, with the length of its slots
+pub struct SlotLayout<'a> {
+    pub times: &'a [Vec<Option<i64>>],
+    pub slot_length: u32,
+}
+
+/// Carries `schedule` and `if_needed` from the `from` layout over to `to` by
+/// the time each slot stands for. A new slot takes everyone's level from the
+/// old slots it overlaps, a finer one just copies the slot it's cut from.
+/// Slots only `to` has are left empty.
+pub fn resample(
+    schedule: &Grid,
+    if_needed: &Grid,
+    from: &SlotLayout,
+    to: &SlotLayout,
+    rule: MergeRule,
+) -> (Grid, Grid) {
+    let mut old_slots: BTreeMap<i64, (usize, usize)> = BTreeMap::new();
+    for (day_index, times) in from.times.iter().enumerate() {
+        for (slot, time) in times.iter().enumerate() {
+            if let Some(time) = time {
+                old_slots.insert(*time, (day_index, slot));
+            }
+        }
+    }
+    let participants: BTreeSet<usize> = schedule.iter().flatten().flatten().copied().collect();
+    let level = |p_idx: usize, (day_index, slot): (usize, usize)| {
+        let in_cell = |grid: &Grid| {
+            grid.get(day_index)
+                .and_then(|day| day.get(slot))
+                .is_some_and(|cell| cell.contains(&p_idx))
+        };
+        if !in_cell(schedule) {
+            UNAVAILABLE
+        } else if in_cell(if_needed) {
+            IF_NEEDED
+        } else {
+            AVAILABLE
+        }
+    };
+
+    let (from_length, to_length) = (i64::from(from.slot_length), i64::from(to.slot_length));
+    let mut new_schedule = Vec::with_capacity(to.times.len());
+    let mut new_if_needed = Vec::with_capacity(to.times.len());
+    for times in to.times {
+        let (day, day_if_needed): (Vec<_>, Vec<_>) = times
+            .iter()
+            .map(|time| {
+                let Some(time) = *time else {
+                    return (Vec::new(), Vec::new());
+                };
+                let overlapping: Vec<(i64, (usize, usize))> = old_slots
+                    .range(time - from_length + 1..time + to_length)
+                    .map(|(&start, &cell)| (start, cell))
+                    .collect();
+                // Parts of the new slot no old slot covers count as unavailable
+                let mut covered_to = time;
+                for &(start, _) in &overlapping {
+                    if start <= covered_to {
+                        covered_to = covered_to.max(start + from_length);
+                    }
+                }
+                let covered = covered_to >= time + to_length;
+
+                let mut available = Vec::new();
+                let mut only_if_needed = Vec::new();
+                for &p_idx in &participants {
+                    let levels = overlapping.iter().map(|&(_, cell)| level(p_idx, cell));
+                    let merged = match rule {
+                        MergeRule::All if covered => levels.min().unwrap_or(UNAVAILABLE),
+                        MergeRule::All => UNAVAILABLE,
+                        MergeRule::Any => levels.max().unwrap_or(UNAVAILABLE),
+                    };
+                    if merged != UNAVAILABLE {
+                        available.push(p_idx);
+                    }
+                    if merged == IF_NEEDED {
+                        only_if_needed.push(p_idx);
+                    }
+                }
+                (available, only_if_needed)
+            })
+            .unzip();
+        new_schedule.push(day);
+        new_if_needed.push(day_if_needed);
+    }
+    (new_schedule, new_if_needed)
+}
+
+/// The finalized range in the `to` layout, if all of it is still there in one piece
+pub fn remap_finalized(
+    finalized: &Finalized,
+    from: &SlotLayout,
+    to: &SlotLayout,
+) -> Option<Finalized> {
+    let times = from
+        .times
+        .get(finalized.day_index)?
+        .get(finalized.start_slot..finalized.end_slot)?;
+    let start = (*times.first()?)?;
+    let end = (*times.last()?)? + i64::from(from.slot_length);
+    let to_length = i64::from(to.slot_length);
+    if to_length == 0 || (end - start) % to_length != 0 {
+        return None;
+    }
+    let slot_count = ((end - start) / to_length) as usize;
+
+    to.times.iter().enumerate().find_map(|(day_index, to_times)| {
+        let start_slot = to_times.iter().position(|time| *time == Some(start))?;
+        let end_slot = start_slot + slot_count;
+        let in_one_piece = to_times
+            .get(start_slot..end_slot)?
+            .iter()
+            .enumerate()
+            .all(|(i, time)| *time == Some(start + i as i64 * to_length));
+        in_one_piece.then_some(Finalized {
+            day_index,
+            start_slot,
+            end_slot,
+            lock_edits: finalized.lock_edits,
+        })
+    })
+}
+
+/// The instant `minute` past midnight of `date` is in `tz`. Wall clock times
+/// skipped by a DST change are read an hour later.
+pub fn local_instant(tz: Tz, date: NaiveDate, minute: u32) -> Option<DateTime<Utc>> {
+    let local = date.and_hms_opt(0, 0, 0)? + Duration::minutes(i64::from(minute));
+    tz.from_local_datetime(&local)
+        .earliest()
+        .or_else(|| tz.from_local_datetime(&(local + Duration::hours(1))).earliest())
+        .map(|at| at.with_timezone(&Utc))
+}
+
+/// `tz`'s UTC offset in minutes on each date, at that date's minute past midnight
+pub fn utc_offsets(tz: Tz, dates: &[Option<NaiveDate>], minutes: &[u32]) -> Vec<Option<i32>> {
+    dates
+        .iter()
+        .zip(minutes)
+        .map(|(date, &minute)| {
+            let at = local_instant(tz, (*date)?, minute)?;
+            Some(at.with_timezone(&tz).offset().fix().local_minus_utc() / 60)
+        })
+        .collect()
+}
+
+/// Slots starting at a wall clock time `tz` skips or goes through twice
+pub fn dst_slots(
+    tz: Tz,
+    dates: &[Option<NaiveDate>],
+    schedule: &Grid,
+    windows: &[TimeWindow],
+) -> Vec<DstSlot> {
+    let mut dst_slots = Vec::new();
+    for ((day_index, day), window) in schedule.iter().enumerate().zip(windows) {
+        let Some(midnight) = dates
+            .get(day_index)
+            .copied()
+            .flatten()
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+        else {
+            continue;
+        };
+        for slot in 0..day.len() {
+            let local = midnight + Duration::minutes(i64::from(window.slot_start(slot)));
+            let kind = match tz.from_local_datetime(&local) {
+                LocalResult::None => DstKind::Skipped,
+                LocalResult::Ambiguous(..) => DstKind::Repeated,
+                LocalResult::Single(_) => continue,
+            };
+            dst_slots.push(DstSlot {
+                day_index,
+                slot,
+                kind,
+            });
+        }
+    }
+    dst_slots
+}
+
+/// Where the room's cells land for a viewer in another zone: columns are the
+/// viewer's dates, rows the viewer's times of day, and cells no room slot
+/// falls on are left empty.
+pub struct ViewerLayout {
+    pub dates: Vec<NaiveDate>,
+    /// Minutes since the viewer's midnight, ascending
+    pub minutes: Vec<u32>,
+    /// `cells[column][row]`, the room's `(day_index, slot)` shown there
+    pub cells: Vec<Vec<Option<(usize, usize)>>>,
+}
+
+impl ViewerLayout {
+    pub fn new(
+        room: &Room,
+        schedule: &Grid,
+        viewer_tz: Tz,
+        today: NaiveDate,
+    ) -> Result<Self, serde_json::Error> {
+        let room_tz: Tz = room.timezone.parse().unwrap_or(Tz::UTC);
+        let windows = column_windows(room)?;
+        let room_dates = column_dates(room, today)?;
+
+        let mut placed = Vec::new();
+        for ((day_index, day), window) in schedule.iter().enumerate().zip(&windows) {
+            let Some(date) = room_dates.get(day_index).copied().flatten() else {
+                continue;
+            };
+            for slot in 0..day.len() {
+                let start = window.slot_start(slot);
+                let skipped = date.and_hms_opt(0, 0, 0).is_some_and(|midnight| {
+                    let local = midnight + Duration::minutes(i64::from(start));
+                    matches!(room_tz.from_local_datetime(&local), LocalResult::None)
+                });
+                if let Some(at) = local_instant(room_tz, date, start) {
+                    let local = at.with_timezone(&viewer_tz).naive_local();
+                    let minute = local.num_seconds_from_midnight() / 60;
+                    placed.push((local.date(), minute, day_index, slot, skipped));
+                }
+            }
+        }
+        // A slot DST skips is read an hour later, on top of the real slot there
+        // if the window goes on, which has to win the cell
+        placed.sort_by_key(|&(.., skipped)| skipped);
+
+        let mut dates: Vec<NaiveDate> = placed.iter().map(|&(date, ..)| date).collect();
+        dates.sort();
+        dates.dedup();
+        let mut minutes: Vec<u32> = placed.iter().map(|&(_, minute, ..)| minute).collect();
+        minutes.sort();
+        minutes.dedup();
+
+        let mut cells = vec![vec![None; minutes.len()]; dates.len()];
+        for (date, minute, day_index, slot, _) in placed {
+            let (Ok(column), Ok(row)) = (dates.binary_search(&date), minutes.binary_search(&minute))
+            else {
+                continue;
+            };
+            // The viewer's repeated hour can put two room slots on one cell, the earlier stays
+            cells[column][row].get_or_insert((day_index, slot));
+        }
+
+        Ok(Self {
+            dates,
+            minutes,
+            cells,
+        })
+    }
+
+    /// Room-shaped `grid` as the viewer sees it
+    pub fn to_viewer<T: Clone>(&self, grid: &[Vec<T>], empty: T) -> Vec<Vec<T>> {
+        self.cells
+            .iter()
+            .map(|column| {
+                column
+                    .iter()
+                    .map(|cell| {
+                        cell.and_then(|(day_index, slot)| grid.get(day_index)?.get(slot).cloned())
+                            .unwrap_or_else(|| empty.clone())
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Writes a viewer-shaped `grid` back over the room-shaped `base`. Room
+    /// cells the viewer can't see keep their value.
+    pub fn to_room<T: Clone>(&self, grid: &[Vec<T>], mut base: Vec<Vec<T>>) -> Vec<Vec<T>> {
+        for (column, cells) in self.cells.iter().enumerate() {
+            for (row, cell) in cells.iter().enumerate() {
+                let (Some((day_index, slot)), Some(value)) =
+                    (cell, grid.get(column).and_then(|column| column.get(row)))
+                else {
+                    continue;
+                };
+                if let Some(target) = base.get_mut(*day_index).and_then(|day| day.get_mut(*slot)) {
+                    *target = value.clone();
+                }
+            }
+        }
+        base
+    }
+
+    /// Moves room-time marks to where the viewer sees those slots
+    pub fn dst_slots_to_viewer(&self, dst_slots: &[DstSlot]) -> Vec<DstSlot> {
+        let mut moved = Vec::new();
+        for (column, cells) in self.cells.iter().enumerate() {
+            for (row, cell) in cells.iter().enumerate() {
+                let Some((day_index, slot)) = cell else {
+                    continue;
+                };
+                if let Some(dst_slot) = dst_slots
+                    .iter()
+                    .find(|dst_slot| dst_slot.day_index == *day_index && dst_slot.slot == *slot)
+                {
+                    moved.push(DstSlot {
+                        day_index: column,
+                        slot: row,
+                        kind: dst_slot.kind,
+                    });
+                }
+            }
+        }
+        moved
+    }
+
+    /// The viewer's time of the first slot in each column
+    pub fn first_minutes(&self) -> Vec<u32> {
+        self.cells
+            .iter()
+            .map(|column| {
+                column
+                    .iter()
+                    .position(Option::is_some)
+                    .and_then(|row| self.minutes.get(row).copied())
+                    .unwrap_or(0)
+            })
+            .collect()
+    }
+
+    pub fn has_slot(&self) -> Vec<Vec<bool>> {
+        self.cells
+            .iter()
+            .map(|column| column.iter().map(|cell| cell.is_some()).collect())
+            .collect()
+    }
+}
+
+/// `HH:MM` for minutes since midnight, `+1` marking the next day's
+pub fn format_minute(minute: u32) -> String {
+    let days = minute / MINUTES_PER_DAY;
+    let label = format!("{:02}:{:02}", (minute / 60) % 24, minute % 60);
+    if days > 0 {
+        format!("{}+{}", label, days)
+    } else {
+        label
+    }
+}
+
+/// A run of slots in one column where everyone in `available` is free throughout
+pub struct Candidate {
+    pub day_index: usize,
+    pub start_slot: usize,
+    /// Exclusive
+    pub end_slot: usize,
+    pub available: Vec<usize>,
+    /// Those of `available` who are only free if needed for part of the run
+    pub if_needed: Vec<usize>,
+}
+
+fn is_free_in(cell: &[usize], people: &BTreeSet<usize>) -> bool {
+    people.iter().all(|p| cell.contains(p))
+}
+
+/// Finds runs of at least `run_length` slots, each as long as its group of
+/// people stays free, ranked by how many are free for the whole run, then by
+/// how few of them only if needed, then by length, then chronologically.
+/// Runs missing anyone in `required` aren't viable and are left out.
+pub fn best_times(
+    schedule: &Grid,
+    if_needed: &Grid,
+    required: &[usize],
+    run_length: usize,
+) -> Vec<Candidate> {
+    let mut candidates: Vec<Candidate> = Vec::new();
+    if run_length == 0 {
+        return candidates;
+    }
+
+    for (day_index, day) in schedule.iter().enumerate() {
+        if day.len() < run_length {
+            continue;
+        }
+
+        let mut seen: BTreeSet<(usize, usize, Vec<usize>)> = BTreeSet::new();
+
+        for start in 0..=(day.len() - run_length) {
+            let people: BTreeSet<usize> = day[start]
+                .iter()
+                .copied()
+                .filter(|p| day[start + 1..start + run_length].iter().all(|cell| cell.contains(p)))
+                .collect();
+            if people.is_empty() || !required.iter().all(|p| people.contains(p)) {
+                continue;
+            }
+
+            // Grow the run both ways for as long as the same people stay free
+            let mut start_slot = start;
+            while start_slot > 0 && is_free_in(&day[start_slot - 1], &people) {
+                start_slot -= 1;
+            }
+            let mut end_slot = start + run_length;
+            while end_slot < day.len() && is_free_in(&day[end_slot], &people) {
+                end_slot += 1;
+            }
+
+            let available: Vec<usize> = people.into_iter().collect();
+            if seen.insert((start_slot, end_slot, available.clone())) {
+                let if_needed_day = if_needed.get(day_index);
+                let if_needed: Vec<usize> = available
+                    .iter()
+                    .copied()
+                    .filter(|p| {
+                        (start_slot..end_slot).any(|slot| {
+                            if_needed_day
+                                .and_then(|day| day.get(slot))
+                                .is_some_and(|cell| cell.contains(p))
+                        })
+                    })
+                    .collect();
+
+                candidates.push(Candidate {
+                    day_index,
+                    start_slot,
+                    end_slot,
+                    available,
+                    if_needed,
+                });
+            }
+        }
+    }
+
+    candidates.sort_by(|a, b| {
+        b.available
+            .len()
+            .cmp(&a.available.len())
+            .then(a.if_needed.len().cmp(&b.if_needed.len()))
+            .then((b.end_slot - b.start_slot).cmp(&(a.end_slot - a.start_slot)))
+            .then(a.day_index.cmp(&b.day_index))
+            .then(a.start_slot.cmp(&b.start_slot))
+    });
+
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preferences_read_flags_and_levels() {
+        let preferences: Vec<Preference> =
+            serde_json::from_str("[true, false, 0, 1, 2, 5]").unwrap();
+        let levels: Vec<u8> = preferences.into_iter().map(Preference::level).collect();
+        assert_eq!(
+            levels,
+            vec![AVAILABLE, UNAVAILABLE, UNAVAILABLE, IF_NEEDED, AVAILABLE, AVAILABLE]
+        );
+    }
+
+    #[test]
+    fn preferences_round_trip_through_the_grids() {
+        let mut schedule: Grid = vec![vec![vec![0, 1], vec![0], vec![1]], vec![vec![1], vec![]]];
+        let mut if_needed: Grid = vec![vec![vec![1], vec![], vec![]], vec![vec![], vec![]]];
+        let preferences: Vec<Vec<Preference>> =
+            serde_json::from_str("[[2, true, 1], [false]]").unwrap();
+
+        set_preferences(&mut schedule, &mut if_needed, 1, &preferences);
+
+        // Person 0 is untouched, the cell left out counts as unavailable
+        assert_eq!(schedule, vec![vec![vec![0, 1], vec![0, 1], vec![1]], vec![vec![], vec![]]]);
+        assert_eq!(if_needed, vec![vec![vec![], vec![], vec![1]], vec![vec![], vec![]]]);
+        assert_eq!(
+            preferences_of(&schedule, &if_needed, Some(1)),
+            vec![vec![AVAILABLE, AVAILABLE, IF_NEEDED], vec![UNAVAILABLE, UNAVAILABLE]]
+        );
+        assert_eq!(
+            preferences_of(&schedule, &if_needed, Some(0)),
+            vec![vec![AVAILABLE, AVAILABLE, UNAVAILABLE], vec![UNAVAILABLE, UNAVAILABLE]]
+        );
+        assert_eq!(
+            preferences_of(&schedule, &if_needed, None),
+            vec![vec![UNAVAILABLE; 3], vec![UNAVAILABLE; 2]]
+        );
+    }
+
+    fn layout(times: &[Vec<Option<i64>>], slot_length: u32) -> SlotLayout<'_> {
+        SlotLayout { times, slot_length }
+    }
+
+    fn hours(starts: &[i64]) -> Vec<Vec<Option<i64>>> {
+        vec![starts.iter().map(|&start| Some(start)).collect()]
+    }
+
+    #[test]
+    fn resample_splits_hours_into_half_hours() {
+        let schedule = vec![vec![vec![0, 1], vec![0]]];
+        let if_needed = vec![vec![vec![1], vec![0]]];
+        let (from, to) = (hours(&[540, 600]), hours(&[540, 570, 600, 630]));
+
+        for rule in [MergeRule::All, MergeRule::Any] {
+            let (new_schedule, new_if_needed) =
+                resample(&schedule, &if_needed, &layout(&from, 60), &layout(&to, 30), rule);
+            assert_eq!(new_schedule, vec![vec![vec![0, 1], vec![0, 1], vec![0], vec![0]]]);
+            assert_eq!(new_if_needed, vec![vec![vec![1], vec![1], vec![0], vec![0]]]);
+        }
+    }
+
+    #[test]
+    fn resample_merges_half_hours_by_rule() {
+        let schedule = vec![vec![vec![0, 1], vec![0], vec![0, 1], vec![1]]];
+        let if_needed = vec![vec![vec![1], vec![], vec![0], vec![]]];
+        let from = hours(&[540, 570, 600, 630]);
+        // The last column's hour is only half covered by the old slots
+        let to = vec![vec![Some(540), Some(600)], vec![Some(630)]];
+
+        let (all, all_if_needed) = resample(
+            &schedule,
+            &if_needed,
+            &layout(&from, 30),
+            &layout(&to, 60),
+            MergeRule::All,
+        );
+        assert_eq!(all, vec![vec![vec![0], vec![1]], vec![vec![]]]);
+        let nobody: Grid = vec![vec![vec![], vec![]], vec![vec![]]];
+        assert_eq!(all_if_needed, nobody);
+
+        let (any, any_if_needed) = resample(
+            &schedule,
+            &if_needed,
+            &layout(&from, 30),
+            &layout(&to, 60),
+            MergeRule::Any,
+        );
+        assert_eq!(any, vec![vec![vec![0, 1], vec![0, 1]], vec![vec![1]]]);
+        assert_eq!(any_if_needed, vec![vec![vec![1], vec![0]], vec![vec![]]]);
+    }
+
+    #[test]
+    fn remap_finalized_follows_the_slot_length() {
+        let (half_hours, whole_hours) = (hours(&[540, 570, 600, 630]), hours(&[540, 600]));
+        let finalized = |start_slot, end_slot| Finalized {
+            day_index: 0,
+            start_slot,
+            end_slot,
+            lock_edits: true,
+        };
+
+        let merged = remap_finalized(
+            &finalized(0, 2),
+            &layout(&half_hours, 30),
+            &layout(&whole_hours, 60),
+        )
+        .unwrap();
+        assert_eq!((merged.day_index, merged.start_slot, merged.end_slot), (0, 0, 1));
+        assert!(merged.lock_edits);
+
+        let split = remap_finalized(
+            &finalized(1, 2),
+            &layout(&whole_hours, 60),
+            &layout(&half_hours, 30),
+        )
+        .unwrap();
+        assert_eq!((split.day_index, split.start_slot, split.end_slot), (0, 2, 4));
+
+        // 9:30 to 10:30 doesn't start on a whole hour
+        assert!(remap_finalized(
+            &finalized(1, 3),
+            &layout(&half_hours, 30),
+            &layout(&whole_hours, 60),
+        )
+        .is_none());
+    }
+
+    fn dated_room(dates: &[&str], start_minute: u16, end_minute: u16, timezone: &str) -> Room {
+        Room {
+            dates: serde_json::to_string(dates).unwrap(),
+            start_minute,
+            end_minute,
+            timezone: timezone.to_string(),
+            ..Room::for_test()
+        }
+    }
+
+    fn date(value: &str) -> NaiveDate {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn overnight_window_across_spring_forward() {
+        // 23:00 to 03:00, 2:00 doesn't exist on the night of 8 March
+        let room = dated_room(&["Sat Mar 07 2026"], 23 * 60, 3 * 60, "America/New_York");
+        let windows = column_windows(&room).unwrap();
+        let schedule: Grid = vec![vec![Vec::new(); windows[0].slot_count()]];
+        let dates = column_dates(&room, date("2026-01-01")).unwrap();
+
+        let skipped = dst_slots(Tz::America__New_York, &dates, &schedule, &windows);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!((skipped[0].day_index, skipped[0].slot), (0, 3));
+        assert!(skipped[0].kind == DstKind::Skipped);
+
+        // The skipped slot is read an hour later, 03:00 EDT
+        let viewer = ViewerLayout::new(&room, &schedule, Tz::UTC, date("2026-01-01")).unwrap();
+        assert_eq!(viewer.dates, vec![date("2026-03-08")]);
+        assert_eq!(viewer.minutes, vec![4 * 60, 5 * 60, 6 * 60, 7 * 60]);
+        assert_eq!(
+            viewer.cells,
+            vec![vec![Some((0, 0)), Some((0, 1)), Some((0, 2)), Some((0, 3))]]
+        );
+        assert_eq!(viewer.first_minutes(), vec![4 * 60]);
+
+        let moved = viewer.dst_slots_to_viewer(&skipped);
+        assert_eq!((moved[0].day_index, moved[0].slot), (0, 3));
+    }
+
+    #[test]
+    fn real_slot_wins_over_the_skipped_one() {
+        // 23:00 to 04:00, the missing 2:00 is read as 3:00 EDT like the slot after it
+        let room = dated_room(&["Sat Mar 07 2026"], 23 * 60, 4 * 60, "America/New_York");
+        let schedule: Grid = vec![vec![Vec::new(); 5]];
+
+        let viewer = ViewerLayout::new(&room, &schedule, Tz::UTC, date("2026-01-01")).unwrap();
+        assert_eq!(viewer.minutes, vec![4 * 60, 5 * 60, 6 * 60, 7 * 60]);
+        assert_eq!(
+            viewer.cells,
+            vec![vec![Some((0, 0)), Some((0, 1)), Some((0, 2)), Some((0, 4))]]
+        );
+        assert_eq!(
+            viewer.to_room(&[vec![1, 1, 1, 1]], vec![vec![0; 5]]),
+            vec![vec![1, 1, 1, 0, 1]]
+        );
+    }
+
+    #[test]
+    fn overnight_window_across_fall_back() {
+        // 1:00 comes twice on the night of 1 November, the first is used
+        let room = dated_room(&["Sat Oct 31 2026"], 23 * 60, 3 * 60, "America/New_York");
+        let windows = column_windows(&room).unwrap();
+        let schedule: Grid = vec![vec![Vec::new(); windows[0].slot_count()]];
+        let dates = column_dates(&room, date("2026-01-01")).unwrap();
+
+        let repeated = dst_slots(Tz::America__New_York, &dates, &schedule, &windows);
+        assert_eq!(repeated.len(), 1);
+        assert_eq!((repeated[0].day_index, repeated[0].slot), (0, 2));
+        assert!(repeated[0].kind == DstKind::Repeated);
+
+        let viewer = ViewerLayout::new(&room, &schedule, Tz::UTC, date("2026-01-01")).unwrap();
+        assert_eq!(viewer.dates, vec![date("2026-11-01")]);
+        assert_eq!(viewer.minutes, vec![3 * 60, 4 * 60, 5 * 60, 7 * 60]);
+    }
+
+    #[test]
+    fn viewer_grid_round_trips_to_the_room() {
+        // 22:00 to 02:00 in Tokyo is 13:00 to 17:00 UTC the same day
+        let room = dated_room(
+            &["Mon Mar 02 2026", "Tue Mar 03 2026"],
+            22 * 60,
+            2 * 60,
+            "Asia/Tokyo",
+        );
+        let schedule: Grid = vec![vec![Vec::new(); 4]; 2];
+        let viewer = ViewerLayout::new(&room, &schedule, Tz::UTC, date("2026-01-01")).unwrap();
+        assert_eq!(viewer.dates, vec![date("2026-03-02"), date("2026-03-03")]);
+        assert_eq!(viewer.minutes, vec![13 * 60, 14 * 60, 15 * 60, 16 * 60]);
+
+        let room_grid = vec![vec![1, 2, 3, 4], vec![5, 6, 7, 8]];
+        let viewer_grid = viewer.to_viewer(&room_grid, 0);
+        assert_eq!(viewer_grid, room_grid);
+        assert_eq!(viewer.to_room(&viewer_grid, vec![vec![0; 4]; 2]), room_grid);
+    }
+
+    #[test]
+    fn poll_answers_follow_their_option() {
+        let options = |options: &[&str]| -> Vec<String> {
+            options.iter().map(|option| option.to_string()).collect()
+        };
+        let columns = option_columns(
+            &options(&["Pizza", "Sushi", "Tacos"]),
+            &options(&["Tacos", "Curry", "Pizza"]),
+        );
+        assert_eq!(columns, vec![Some(2), None, Some(0)]);
+
+        let schedule: Grid = vec![vec![vec![0, 1]], vec![vec![1]], vec![vec![0]]];
+        assert_eq!(
+            select_columns(&schedule, &columns),
+            vec![vec![vec![0]], vec![vec![]], vec![vec![0, 1]]]
+        );
+    }
+
+    #[test]
+    fn poll_options_are_single_untimed_slots() {
+        // Hours and slot length stored for the room don't apply to a poll
+        let room = Room {
+            schedule_type: 3,
+            options: Some(r#"["Pizza","Sushi"]"#.to_string()),
+            day_windows: Some(r#"[{"start_minute":0,"end_minute":60}]"#.to_string()),
+            ..Room::for_test()
+        };
+        let windows = column_windows(&room).unwrap();
+        assert!(windows.iter().all(|window| *window == TimeWindow::OPTION));
+        assert_eq!(windows.iter().map(TimeWindow::slot_count).collect::<Vec<_>>(), vec![1, 1]);
+
+        let schedule: Grid = vec![vec![vec![0]], vec![vec![0, 1]]];
+        let candidates = best_times(&schedule, &Vec::new(), &[], 1);
+        assert_eq!(runs(&candidates), vec![(1, 0, 1, vec![0, 1]), (0, 0, 1, vec![0])]);
+    }
+
+    fn runs(candidates: &[Candidate]) -> Vec<(usize, usize, usize, Vec<usize>)> {
+        candidates
+            .iter()
+            .map(|c| (c.day_index, c.start_slot, c.end_slot, c.available.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn best_times_grow_runs_and_drop_repeats() {
+        let schedule: Grid = vec![vec![vec![0], vec![0, 1], vec![0, 1], vec![0]]];
+
+        // Starting at slot 0 and slot 2 both grow into the same run of person 0
+        let candidates = best_times(&schedule, &Vec::new(), &[], 2);
+        assert_eq!(
+            runs(&candidates),
+            vec![(0, 1, 3, vec![0, 1]), (0, 0, 4, vec![0])]
+        );
+
+        assert!(best_times(&schedule, &Vec::new(), &[], 5).is_empty());
+        assert!(best_times(&schedule, &Vec::new(), &[], 0).is_empty());
+    }
+
+    #[test]
+    fn best_times_rank_if_needed_then_length_then_time() {
+        let both = || vec![0, 1];
+        let schedule: Grid = vec![
+            vec![both(), both(), vec![], both(), both(), both()],
+            vec![both(), both(), both(), vec![], vec![], vec![]],
+            vec![both(), both(), vec![], vec![], vec![], vec![]],
+        ];
+        let if_needed: Grid = vec![vec![vec![1], vec![], vec![], vec![], vec![], vec![]]];
+
+        let candidates = best_times(&schedule, &if_needed, &[], 2);
+        assert_eq!(
+            runs(&candidates),
+            vec![
+                (0, 3, 6, both()),
+                (1, 0, 3, both()),
+                (2, 0, 2, both()),
+                (0, 0, 2, both()),
+            ]
+        );
+        assert_eq!(candidates[3].if_needed, vec![1]);
+        assert!(candidates[..3].iter().all(|c| c.if_needed.is_empty()));
+    }
+
+    #[test]
+    fn best_times_leave_out_runs_missing_someone_required() {
+        let schedule: Grid = vec![vec![vec![0, 1], vec![0, 1], vec![0], vec![0]]];
+
+        assert_eq!(
+            runs(&best_times(&schedule, &Vec::new(), &[1], 2)),
+            vec![(0, 0, 2, vec![0, 1])]
+        );
+        assert!(best_times(&schedule, &Vec::new(), &[2], 2).is_empty());
+    }
+
+    fn dates_of(dates: &[&str]) -> Vec<Option<NaiveDate>> {
+        dates.iter().map(|date| parse_room_date(date)).collect()
+    }
+
+    #[test]
+    fn weeks_are_counted_from_a_mid_week_start() {
+        // A Wednesday start puts the next Monday in the first week still
+        let dates = week_dates("Wed May 29 2024", &[1, 3], 2).unwrap();
+        assert_eq!(
+            dates,
+            vec!["Wed May 29 2024", "Mon Jun 03 2024", "Wed Jun 05 2024", "Mon Jun 10 2024"]
+        );
+        let dates = dates_of(&dates.iter().map(String::as_str).collect::<Vec<_>>());
+        assert_eq!(
+            column_weeks(dates[0].unwrap(), &dates),
+            vec![Some(0), Some(0), Some(1), Some(1)]
+        );
+    }
+
+    #[test]
+    fn week_dates_reject_out_of_range_counts() {
+        assert_eq!(week_dates("Mon Jun 03 2024", &[1], 0), None);
+        assert_eq!(week_dates("Mon Jun 03 2024", &[1], MAX_WEEKS + 1), None);
+        assert_eq!(
+            week_dates("Mon Jun 03 2024", &[1], MAX_WEEKS).map(|dates| dates.len()),
+            Some(usize::from(MAX_WEEKS))
+        );
+        assert_eq!(week_dates("Mon Jun 03 2024", &[7], 1), None);
+        assert_eq!(week_dates("Mon Jun 03 2024", &[], 1), None);
+        assert_eq!(week_dates("2024-06-03", &[1], 1), None);
+    }
+
+    #[test]
+    fn copy_week_goes_to_every_other_week_without_targets() {
+        let dates = dates_of(&["Mon Jun 03 2024", "Mon Jun 10 2024", "Mon Jun 17 2024"]);
+        let windows = vec![TimeWindow::new(540, 660, 60); 3];
+        let source = vec![AVAILABLE, IF_NEEDED];
+        let preferences = || vec![vec![UNAVAILABLE; 2], source.clone(), vec![UNAVAILABLE; 2]];
+
+        let mut copied = preferences();
+        copy_week(&mut copied, &dates, &windows, 1, None);
+        assert_eq!(copied, vec![source.clone(); 3]);
+
+        let mut copied = preferences();
+        copy_week(&mut copied, &dates, &windows, 1, Some(&[2]));
+        assert_eq!(copied, vec![vec![UNAVAILABLE; 2], source.clone(), source.clone()]);
+    }
+
+    #[test]
+    fn copy_week_matches_slots_by_time_of_day() {
+        // 9:00-12:00 onto 10:00-14:00 on the Monday, 8:00-10:00 on the Tuesday
+        let dates = dates_of(&[
+            "Mon Jun 03 2024",
+            "Tue Jun 04 2024",
+            "Mon Jun 10 2024",
+            "Tue Jun 11 2024",
+        ]);
+        let windows = vec![
+            TimeWindow::new(540, 720, 60),
+            TimeWindow::new(540, 720, 60),
+            TimeWindow::new(600, 840, 60),
+            TimeWindow::new(480, 600, 60),
+        ];
+        let mut preferences = vec![
+            vec![IF_NEEDED, AVAILABLE, AVAILABLE],
+            vec![AVAILABLE, IF_NEEDED, UNAVAILABLE],
+            vec![UNAVAILABLE, UNAVAILABLE, IF_NEEDED, IF_NEEDED],
+            vec![IF_NEEDED, UNAVAILABLE],
+        ];
+        copy_week(&mut preferences, &dates, &windows, 0, None);
+        assert_eq!(preferences[2], vec![AVAILABLE, AVAILABLE, IF_NEEDED, IF_NEEDED]);
+        assert_eq!(preferences[3], vec![IF_NEEDED, AVAILABLE]);
+    }
+}
@@ -1,11 +1,14 @@
 use crate::models::State;
 
+use chrono_tz::Tz;
 use num_bigint::BigUint;
+use serde::Deserialize;
 use sha2::{Digest, Sha256};
 use std::ops::Div;
 use std::ops::Rem;
 use std::time::{SystemTime, UNIX_EPOCH};
 use tide::Request;
+use time_new::format_description::well_known::Rfc3339;
 use uuid::Uuid;
 
 pub fn generate_auth_token() -> String {
@@ -20,6 +23,26 @@ pub fn generate_auth_token() -> String {
     format!("{:x}", hash)
 }
 
+pub fn format_timestamp(timestamp: time_new::OffsetDateTime) -> String {
+    timestamp.format(&Rfc3339).unwrap_or_default()
+}
+
+/// `text` safe to put in HTML, attribute values included
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
 pub fn generate_id(ip: &str, len: usize) -> String {
     let timestamp = SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -52,6 +75,22 @@ pub fn generate_id(ip: &str, len: usize) -> String {
     id[..id.len().min(len)].to_string()
 }
 
+/// `FRONTEND_URL` plus any extra origins listed comma-separated in `ALLOWED_ORIGINS`
+pub fn allowed_origins() -> Result<Vec<String>, std::env::VarError> {
+    let mut origins = vec![std::env::var("FRONTEND_URL")?];
+
+    if let Ok(extra) = std::env::var("ALLOWED_ORIGINS") {
+        origins.extend(
+            extra
+                .split(',')
+                .map(|origin| origin.trim().trim_end_matches('/').to_string())
+                .filter(|origin| !origin.is_empty()),
+        );
+    }
+
+    Ok(origins)
+}
+
 pub async fn get_user_uid_from_cookie(req: &Request<State>) -> Option<String> {
     let mut user_uid = None;
     let auth_cookie = req.cookie("auth_token");
@@ -62,7 +101,7 @@ pub async fn get_user_uid_from_cookie(req: &Request<State>) -> Option<String> {
         user_uid = match sqlx::query!(
             r#"
                 SELECT * FROM users
-                WHERE auth_token=?
+                WHERE auth_token=? AND auth_token_expires_at > NOW()
                 "#,
             client_auth_token
         )
@@ -76,3 +115,57 @@ pub async fn get_user_uid_from_cookie(req: &Request<State>) -> Option<String> {
 
     user_uid
 }
+
+pub fn get_read_only_token(req: &Request<State>) -> Option<String> {
+    #[derive(Deserialize)]
+    struct ReadOnlyQuery {
+        view: Option<String>,
+    }
+
+    req.query::<ReadOnlyQuery>()
+        .ok()
+        .and_then(|query| query.view)
+        .filter(|token| !token.is_empty())
+}
+
+/// Zone from `?tz=`, an unknown zone is an error
+pub fn get_viewer_timezone(req: &Request<State>) -> Result<Option<Tz>, chrono_tz::ParseError> {
+    #[derive(Deserialize)]
+    struct TimezoneQuery {
+        tz: Option<String>,
+    }
+
+    match req.query::<TimezoneQuery>().ok().and_then(|query| query.tz) {
+        Some(tz) if !tz.is_empty() => tz.parse().map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Whether the user still holds an unexpired, unrevoked token. Open websockets
+/// poll this so revocation and expiry also end live connections.
+pub async fn user_has_live_token(state: &State, user_uid: &str) -> Result<bool, sqlx::Error> {
+    let live: Option<(String,)> = sqlx::query_as(
+        r#"
+        SELECT uid FROM users
+        WHERE uid=? AND auth_token IS NOT NULL AND auth_token_expires_at > NOW()
+        "#,
+    )
+    .bind(user_uid)
+    .fetch_optional(&state.db_pool)
+    .await?;
+
+    Ok(live.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_html_keeps_text_inside_attributes() {
+        assert_eq!(
+            escape_html(r#""><script>alert('x')</script> & co"#),
+            "&quot;&gt;&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt; &amp; co"
+        );
+    }
+}